@@ -7,20 +7,31 @@ use crate::{
     util::{self, hex_from_rgba, SRGBA},
 };
 
-use adw::{builders::ExpanderRowBuilder, traits::ExpanderRowExt, ExpanderRow, StyleManager};
+use adw::{
+    builders::ExpanderRowBuilder,
+    traits::{ActionRowExt, ExpanderRowExt},
+    ActionRow, ExpanderRow, StyleManager,
+};
 use cascade::cascade;
 use gtk4::{
     gdk::{self, RGBA},
-    gio::File,
+    gio::{self, File},
     glib::{self, closure_local},
     prelude::*,
     subclass::prelude::*,
-    Align, Box, Button, ColorButton, CssProvider, Entry, Label, MessageDialog, Orientation,
-    ScrolledWindow, TextView, Window,
+    Align, Box, Button, ColorButton, CssProvider, DropDown, Entry, Label, ListBox, MessageDialog,
+    Orientation, ScrolledWindow, SelectionMode, TextView, Window,
 };
 use relm4_macros::view;
 use std::fmt::Display;
-use user_colors::{colors::ColorOverrides, config::Config};
+use user_colors::{
+    apps::AppThemes,
+    color_scheme::ColorScheme,
+    colors::{ColorGroup, ColorKey, ColorOverrides, CssMode},
+    config::Config,
+    library::{ThemeEntry, ThemeLibrary, ThemeSource},
+    widgets::app_picker::AppPicker,
+};
 mod imp;
 
 glib::wrapper! {
@@ -40,187 +51,6 @@ impl ColorOverridesEditor {
             ..set_orientation(Orientation::Vertical);
         };
 
-        // TODO use i18n for labels
-        let accent_section = ExpanderRow::builder()
-            .name("Accent Colors")
-            .expanded(true)
-            .enable_expansion(true)
-            .title("Accent Colors")
-            .hexpand(true)
-            .build();
-        let (accent_bg_color, accent_bg_color_button) =
-            Self::get_color_button(&self_, "accent_bg_color", "Accent Background Color");
-        accent_section.add_row(&accent_bg_color);
-        let (accent_fg_color, accent_fg_color_button) =
-            Self::get_color_button(&self_, "accent_fg_color", "Accent Foreground Color");
-        accent_section.add_row(&accent_fg_color);
-        let (accent_color, accent_color_button) =
-            Self::get_color_button(&self_, "accent_color", "Accent Color");
-        accent_section.add_row(&accent_color);
-
-        let destructive_section = ExpanderRow::builder()
-            .name("Destructive Colors")
-            .expanded(true)
-            .enable_expansion(true)
-            .title("Destructive Colors")
-            .hexpand(true)
-            .build();
-        let (destructive_bg_color, destructive_bg_color_button) = Self::get_color_button(
-            &self_,
-            "destructive_bg_color",
-            "Destructive Background Color",
-        );
-        destructive_section.add_row(&destructive_bg_color);
-        let (destructive_fg_color, destructive_fg_color_button) = Self::get_color_button(
-            &self_,
-            "destructive_fg_color",
-            "Destructive Foreground Color",
-        );
-        destructive_section.add_row(&destructive_fg_color);
-        let (destructive_color, destructive_color_button) =
-            Self::get_color_button(&self_, "destructive_color", "Destructive Color");
-        destructive_section.add_row(&destructive_color);
-
-        let status_section = ExpanderRow::builder()
-            .name("Status Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Status Colors")
-            .hexpand(true)
-            .build();
-        let (success_color, success_color_button) =
-            Self::get_color_button(&self_, "success_color", "Success Color");
-        status_section.add_row(&success_color);
-        let (success_bg_color, success_bg_color_button) =
-            Self::get_color_button(&self_, "success_bg_color", "Success Background Color");
-        status_section.add_row(&success_bg_color);
-        let (success_fg_color, success_fg_color_button) =
-            Self::get_color_button(&self_, "success_fg_color", "Success Foreground Color");
-        status_section.add_row(&success_fg_color);
-
-        let (warning_color, warning_color_button) =
-            Self::get_color_button(&self_, "warning_color", "Warning Color");
-        status_section.add_row(&warning_color);
-        let (warning_bg_color, warning_bg_color_button) =
-            Self::get_color_button(&self_, "warning_bg_color", "Warning Background Color");
-        status_section.add_row(&warning_bg_color);
-        let (warning_fg_color, warning_fg_color_button) =
-            Self::get_color_button(&self_, "warning_fg_color", "Warning Foreground Color");
-        status_section.add_row(&warning_fg_color);
-
-        let (error_color, error_color_button) =
-            Self::get_color_button(&self_, "error_color", "Error Color");
-        status_section.add_row(&error_color);
-        let (error_bg_color, error_bg_color_button) =
-            Self::get_color_button(&self_, "error_bg_color", "Error Background Color");
-        status_section.add_row(&error_bg_color);
-        let (error_fg_color, error_fg_color_button) =
-            Self::get_color_button(&self_, "error_fg_color", "Error Foreground Color");
-        status_section.add_row(&error_fg_color);
-
-        let content_section = ExpanderRow::builder()
-            .name("Content Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Content Colors")
-            .hexpand(true)
-            .build();
-        let (view_bg_color, view_bg_color_button) =
-            Self::get_color_button(&self_, "view_bg_color", "Widget Base Color");
-        content_section.add_row(&view_bg_color);
-        let (view_fg_color, view_fg_color_button) =
-            Self::get_color_button(&self_, "view_fg_color", "Widget Text Color");
-        content_section.add_row(&view_fg_color);
-
-        let window_section = ExpanderRow::builder()
-            .name("Window Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Window Colors")
-            .hexpand(true)
-            .build();
-        let (window_bg_color, window_bg_color_button) =
-            Self::get_color_button(&self_, "window_bg_color", "Window Background Color");
-        window_section.add_row(&window_bg_color);
-        let (window_fg_color, window_fg_color_button) =
-            Self::get_color_button(&self_, "window_fg_color", "Window Foreground Color");
-        window_section.add_row(&window_fg_color);
-
-        let headerbar_section = ExpanderRow::builder()
-            .name("Headerbar Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Headerbar Colors")
-            .hexpand(true)
-            .build();
-        let (headerbar_bg_color, headerbar_bg_color_button) =
-            Self::get_color_button(&self_, "headerbar_bg_color", "Headerbar Background Color");
-        headerbar_section.add_row(&headerbar_bg_color);
-
-        let (headerbar_fg_color, headerbar_fg_color_button) =
-            Self::get_color_button(&self_, "headerbar_fg_color", "Headerbar Foreground Color");
-        headerbar_section.add_row(&headerbar_fg_color);
-
-        let (headerbar_border_color, headerbar_border_color_button) =
-            Self::get_color_button(&self_, "headerbar_border_color", "Headerbar Border Color");
-        headerbar_section.add_row(&headerbar_border_color);
-
-        let (headerbar_backdrop_color, headerbar_backdrop_color_button) = Self::get_color_button(
-            &self_,
-            "headerbar_backdrop_color",
-            "Headerbar Backdrop Color",
-        );
-        headerbar_section.add_row(&headerbar_backdrop_color);
-
-        let (headerbar_shade_color, headerbar_shade_color_button) =
-            Self::get_color_button(&self_, "headerbar_shade_color", "Shade Color");
-        headerbar_section.add_row(&headerbar_shade_color);
-
-        let card_section = ExpanderRow::builder()
-            .name("Card Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Card Colors")
-            .hexpand(true)
-            .build();
-        let (card_bg_color, card_bg_color_button) =
-            Self::get_color_button(&self_, "card_bg_color", "Card Background Color");
-        card_section.add_row(&card_bg_color);
-        let (card_fg_color, card_fg_color_button) =
-            Self::get_color_button(&self_, "card_fg_color", "Card Foreground Color");
-        card_section.add_row(&card_fg_color);
-        let (card_shade_color, card_shade_color_button) =
-            Self::get_color_button(&self_, "card_shade_color", "Card Shade Color");
-        card_section.add_row(&card_shade_color);
-
-        let popover_section = ExpanderRow::builder()
-            .name("Popover Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Popover Colors")
-            .hexpand(true)
-            .build();
-        let (popover_bg_color, popover_bg_color_button) =
-            Self::get_color_button(&self_, "popover_bg_color", "Popover Background Color");
-        popover_section.add_row(&popover_bg_color);
-        let (popover_fg_color, popover_fg_color_button) =
-            Self::get_color_button(&self_, "popover_fg_color", "Popover Foreground Color");
-        popover_section.add_row(&popover_fg_color);
-
-        let misc_section = ExpanderRow::builder()
-            .name("Miscellaneous Colors")
-            .expanded(false)
-            .enable_expansion(true)
-            .title("Miscellaneous Colors")
-            .hexpand(true)
-            .build();
-        let (scrollbar_outline_color, scrollbar_outline_color_button) =
-            Self::get_color_button(&self_, "scrollbar_outline_color", "Scrollbar Outline Color");
-        misc_section.add_row(&scrollbar_outline_color);
-        let (shade_color, shade_color_button) =
-            Self::get_color_button(&self_, "shade_color", "Shade Color");
-        misc_section.add_row(&shade_color);
-
         let c = Config::load().unwrap_or_default();
 
         view! {
@@ -248,16 +78,6 @@ impl ColorOverridesEditor {
                     set_margin_bottom: 4,
                     set_margin_start: 4,
                     set_margin_end: 4,
-
-                    append: &accent_section,
-                    append: &destructive_section,
-                    append: &status_section,
-                    append: &content_section,
-                    append: &window_section,
-                    append: &headerbar_section,
-                    append: &card_section,
-                    append: &popover_section,
-                    append: &misc_section,
                 },
 
 
@@ -330,9 +150,55 @@ impl ColorOverridesEditor {
                     append: dark_button = &ThemeChooserButton {},
 
                 },
+
+                append = &Label {
+                    set_text: "Installed Themes",
+                    set_halign: Align::Start,
+                    add_css_class: "heading",
+                    set_margin_top: 8,
+                    set_margin_start: 4,
+                },
+
+                append: themes_list = &ListBox {
+                    set_selection_mode: SelectionMode::None,
+                    add_css_class: "boxed-list",
+                    set_margin_start: 4,
+                    set_margin_end: 4,
+                },
+
+                append = &Label {
+                    set_text: "Application Themes",
+                    set_halign: Align::Start,
+                    add_css_class: "heading",
+                    set_margin_top: 8,
+                    set_margin_start: 4,
+                },
+
+                append: apps_list = &ListBox {
+                    set_selection_mode: SelectionMode::None,
+                    add_css_class: "boxed-list",
+                    set_margin_start: 4,
+                    set_margin_end: 4,
+                },
+
+                append: add_app_button = &Button {
+                    set_halign: Align::Start,
+                    set_margin_top: 4,
+                    set_margin_bottom: 4,
+                    set_margin_start: 4,
+                    set_margin_end: 4,
+
+                    set_child = Some(&Label) {
+                        set_text: "Add Application",
+                    }
+                },
             }
         };
 
+        for (app_id, themes) in &c.apps {
+            apps_list.append(&Self::app_row(&apps_list, app_id, themes));
+        }
+
         light_button.connect_closure(
             "file-selected",
             false,
@@ -364,6 +230,7 @@ impl ColorOverridesEditor {
             .build();
 
         self_.append(&scroll_window);
+        self_.append_sections(&color_box);
 
         imp.css_provider.set(provider).unwrap();
 
@@ -377,10 +244,185 @@ impl ColorOverridesEditor {
         self_.connect_name();
         self_.connect_control_buttons();
         self_.connect_file_button();
+        self_.connect_add_app_button(&add_app_button, &apps_list);
+        self_.reload_themes_list(&themes_list);
 
         self_
     }
 
+    // list the installed themes, user themes can be edited and the others copied to the user's
+    // themes
+    fn reload_themes_list(&self, themes_list: &ListBox) {
+        while let Some(row) = themes_list.first_child() {
+            themes_list.remove(&row);
+        }
+        let library = match ThemeLibrary::new() {
+            Ok(library) => library,
+            Err(e) => {
+                log::error!("failed to open the theme library: {e}");
+                return;
+            }
+        };
+        let entries = library.list().unwrap_or_else(|e| {
+            log::error!("failed to list themes: {e}");
+            Vec::new()
+        });
+        for entry in entries {
+            themes_list.append(&self.theme_row(themes_list, &library, entry));
+        }
+    }
+
+    fn theme_row(
+        &self,
+        themes_list: &ListBox,
+        library: &ThemeLibrary,
+        entry: ThemeEntry,
+    ) -> ActionRow {
+        let subtitle = match (entry.source, &entry.pack) {
+            (ThemeSource::User, _) => String::from("User theme"),
+            (ThemeSource::System, Some(pack)) => format!("System theme from {pack}, read-only"),
+            (ThemeSource::System, None) => String::from("System theme, read-only"),
+            (ThemeSource::Builtin, _) => String::from("Built-in theme, read-only"),
+        };
+        let row = ActionRow::builder()
+            .title(&entry.name)
+            .subtitle(&subtitle)
+            .build();
+
+        let button = Button::with_label(if entry.is_read_only() {
+            "Copy to User"
+        } else {
+            "Edit"
+        });
+        button.set_valign(Align::Center);
+        let library = library.clone();
+        button.connect_clicked(
+            glib::clone!(@weak self as self_, @weak themes_list => move |_| {
+                if entry.is_read_only() {
+                    match library.copy_to_user(&entry.name) {
+                        Ok(_) => self_.reload_themes_list(&themes_list),
+                        Err(e) => log::error!("failed to copy {}: {e}", entry.name),
+                    }
+                    return;
+                }
+                match library.load(&entry.name) {
+                    Ok(theme) => self_.load_theme(theme),
+                    Err(e) => log::error!("failed to load {}: {e}", entry.name),
+                }
+            }),
+        );
+        row.add_suffix(&button);
+        row
+    }
+
+    // open an application picker, and add a row for the selected application
+    fn connect_add_app_button(&self, button: &Button, apps_list: &ListBox) {
+        button.connect_clicked(glib::clone!(@weak self as self_, @weak apps_list => move |_| {
+            let picker = AppPicker::new();
+            let window = Window::builder()
+                .title("Add Application")
+                .modal(true)
+                .default_width(360)
+                .default_height(480)
+                .child(&picker)
+                .build();
+            if let Some(root) = self_.root().and_then(|root| root.downcast::<Window>().ok()) {
+                window.set_transient_for(Some(&root));
+            }
+            picker.connect_app_selected(glib::clone!(@weak window, @weak apps_list => move |_, app_id| {
+                let config = Config::load().unwrap_or_default();
+                if !config.apps.contains_key(&app_id) {
+                    // the application uses the desktop themes until others are selected
+                    let themes = AppThemes::default();
+                    match Config::set_app(&app_id, Some(themes.clone())) {
+                        Ok(()) => apps_list.append(&Self::app_row(&apps_list, &app_id, &themes)),
+                        Err(e) => log::error!("failed to add {app_id}: {e}"),
+                    }
+                }
+                window.close();
+            }));
+            window.show();
+        }));
+    }
+
+    // row with the light and dark theme of an application, and a button removing its overrides
+    fn app_row(apps_list: &ListBox, app_id: &str, themes: &AppThemes) -> ActionRow {
+        let title = gio::DesktopAppInfo::new(&format!("{app_id}.desktop"))
+            .map(|app| app.display_name().to_string())
+            .unwrap_or_else(|| app_id.to_string());
+        let row = ActionRow::builder().title(&title).subtitle(app_id).build();
+
+        let names = ColorOverrides::list_names().unwrap_or_default();
+        for color_scheme in [ColorScheme::Light, ColorScheme::Dark] {
+            let dropdown = Self::app_theme_dropdown(app_id, color_scheme, themes, &names);
+            row.add_suffix(&dropdown);
+        }
+
+        let remove_button = Button::from_icon_name("user-trash-symbolic");
+        remove_button.set_valign(Align::Center);
+        remove_button.set_tooltip_text(Some("Remove"));
+        remove_button.add_css_class("flat");
+        let app_id = app_id.to_string();
+        remove_button.connect_clicked(glib::clone!(@weak apps_list, @weak row => move |_| {
+            match Config::set_app(&app_id, None) {
+                Ok(()) => apps_list.remove(&row),
+                Err(e) => log::error!("failed to remove {app_id}: {e}"),
+            }
+        }));
+        row.add_suffix(&remove_button);
+        row
+    }
+
+    // drop down selecting the theme of an application for a color scheme
+    // the first entry selects the desktop theme
+    fn app_theme_dropdown(
+        app_id: &str,
+        color_scheme: ColorScheme,
+        themes: &AppThemes,
+        names: &[String],
+    ) -> DropDown {
+        let mut names = names.to_vec();
+        let selected = themes.theme_name(color_scheme);
+        if let Some(selected) = selected {
+            if !names.iter().any(|n| n == selected) {
+                names.push(selected.to_string());
+            }
+        }
+        let mut items = vec!["Default"];
+        items.extend(names.iter().map(String::as_str));
+        let dropdown = DropDown::from_strings(&items);
+        dropdown.set_valign(Align::Center);
+        dropdown.set_tooltip_text(Some(match color_scheme {
+            ColorScheme::Light => "Light Theme",
+            ColorScheme::Dark => "Dark Theme",
+        }));
+        let position = selected
+            .and_then(|s| names.iter().position(|n| n == s))
+            .map(|i| i + 1)
+            .unwrap_or_default();
+        dropdown.set_selected(position as u32);
+
+        let app_id = app_id.to_string();
+        dropdown.connect_selected_notify(move |dropdown| {
+            let name = match dropdown.selected() as usize {
+                0 => None,
+                i => names.get(i - 1).cloned(),
+            };
+            let mut themes = Config::load()
+                .ok()
+                .and_then(|c| c.apps.get(&app_id).cloned())
+                .unwrap_or_default();
+            match color_scheme {
+                ColorScheme::Light => themes.light = name,
+                ColorScheme::Dark => themes.dark = name,
+            }
+            if let Err(e) = Config::set_app(&app_id, Some(themes)) {
+                log::error!("failed to set the theme of {app_id}: {e}");
+            }
+        });
+        dropdown
+    }
+
     fn connect_name(&self) {
         let imp = imp::ColorOverridesEditor::from_instance(&self);
         imp.name.get().unwrap().connect_changed(
@@ -398,221 +440,57 @@ impl ColorOverridesEditor {
         imp.file_button.get().unwrap().connect_closure(
             "file-selected",
             false,
-            closure_local!(@weak-allow-none imp.name as name, @weak-allow-none imp.theme as theme, @weak-allow-none imp.color_editor as color_editor, @weak-allow-none self as self_ => move |_file_button: ThemeChooserButton, f: File| {
-                if let (Some(theme), Some(name), Some(Ok(t)), Some(color_editor)) = (theme, name, f.path().as_ref().map(|p| ColorOverrides::load(p)), color_editor) {
-                    let name = name.get().unwrap();
-                    name.set_text(&t.name);
-                    theme.replace(t);
-
-                    let color_editor = color_editor.get().unwrap();
-                    let mut c = color_editor.first_child();
-                    while let Some(child) = c  {
-                        color_editor.remove(&child);
-                        c = color_editor.first_child();
-                    }
-                    let accent_section = ExpanderRow::builder()
-                    .name("Accent Colors")
-                    .expanded(true)
-                    .enable_expansion(true)
-                    .title("Accent Colors")
-                    .hexpand(true)
-                    .build();
-                let self_ = self_.unwrap();
-                let (accent_bg_color, accent_bg_color_button) =
-                    Self::get_color_button(&self_, "accent_bg_color", "Accent Background Color");
-                accent_section.add_row(&accent_bg_color);
-                let (accent_fg_color, accent_fg_color_button) =
-                    Self::get_color_button(&self_, "accent_fg_color", "Accent Foreground Color");
-                accent_section.add_row(&accent_fg_color);
-                let (accent_color, accent_color_button) =
-                    Self::get_color_button(&self_, "accent_color", "Accent Color");
-                accent_section.add_row(&accent_color);
-        
-                let destructive_section = ExpanderRow::builder()
-                    .name("Destructive Colors")
-                    .expanded(true)
-                    .enable_expansion(true)
-                    .title("Destructive Colors")
-                    .hexpand(true)
-                    .build();
-                let (destructive_bg_color, destructive_bg_color_button) = Self::get_color_button(
-                    &self_,
-                    "destructive_bg_color",
-                    "Destructive Background Color",
-                );
-                destructive_section.add_row(&destructive_bg_color);
-                let (destructive_fg_color, destructive_fg_color_button) = Self::get_color_button(
-                    &self_,
-                    "destructive_fg_color",
-                    "Destructive Foreground Color",
-                );
-                destructive_section.add_row(&destructive_fg_color);
-                let (destructive_color, destructive_color_button) =
-                    Self::get_color_button(&self_, "destructive_color", "Destructive Color");
-                destructive_section.add_row(&destructive_color);
-        
-                let status_section = ExpanderRow::builder()
-                    .name("Status Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Status Colors")
-                    .hexpand(true)
-                    .build();
-                let (success_color, success_color_button) =
-                    Self::get_color_button(&self_, "success_color", "Success Color");
-                status_section.add_row(&success_color);
-                let (success_bg_color, success_bg_color_button) =
-                    Self::get_color_button(&self_, "success_bg_color", "Success Background Color");
-                status_section.add_row(&success_bg_color);
-                let (success_fg_color, success_fg_color_button) =
-                    Self::get_color_button(&self_, "success_fg_color", "Success Foreground Color");
-                status_section.add_row(&success_fg_color);
-        
-                let (warning_color, warning_color_button) =
-                    Self::get_color_button(&self_, "warning_color", "Warning Color");
-                status_section.add_row(&warning_color);
-                let (warning_bg_color, warning_bg_color_button) =
-                    Self::get_color_button(&self_, "warning_bg_color", "Warning Background Color");
-                status_section.add_row(&warning_bg_color);
-                let (warning_fg_color, warning_fg_color_button) =
-                    Self::get_color_button(&self_, "warning_fg_color", "Warning Foreground Color");
-                status_section.add_row(&warning_fg_color);
-        
-                let (error_color, error_color_button) =
-                    Self::get_color_button(&self_, "error_color", "Error Color");
-                status_section.add_row(&error_color);
-                let (error_bg_color, error_bg_color_button) =
-                    Self::get_color_button(&self_, "error_bg_color", "Error Background Color");
-                status_section.add_row(&error_bg_color);
-                let (error_fg_color, error_fg_color_button) =
-                    Self::get_color_button(&self_, "error_fg_color", "Error Foreground Color");
-                status_section.add_row(&error_fg_color);
-        
-                let content_section = ExpanderRow::builder()
-                    .name("Content Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Content Colors")
-                    .hexpand(true)
-                    .build();
-                let (view_bg_color, view_bg_color_button) =
-                    Self::get_color_button(&self_, "view_bg_color", "Widget Base Color");
-                content_section.add_row(&view_bg_color);
-                let (view_fg_color, view_fg_color_button) =
-                    Self::get_color_button(&self_, "view_fg_color", "Widget Text Color");
-                content_section.add_row(&view_fg_color);
-        
-                let window_section = ExpanderRow::builder()
-                    .name("Window Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Window Colors")
-                    .hexpand(true)
-                    .build();
-                let (window_bg_color, window_bg_color_button) =
-                    Self::get_color_button(&self_, "window_bg_color", "Window Background Color");
-                window_section.add_row(&window_bg_color);
-                let (window_fg_color, window_fg_color_button) =
-                    Self::get_color_button(&self_, "window_fg_color", "Window Foreground Color");
-                window_section.add_row(&window_fg_color);
-        
-                let headerbar_section = ExpanderRow::builder()
-                    .name("Headerbar Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Headerbar Colors")
-                    .hexpand(true)
-                    .build();
-                let (headerbar_bg_color, headerbar_bg_color_button) =
-                    Self::get_color_button(&self_, "headerbar_bg_color", "Headerbar Background Color");
-                headerbar_section.add_row(&headerbar_bg_color);
-        
-                let (headerbar_fg_color, headerbar_fg_color_button) =
-                    Self::get_color_button(&self_, "headerbar_fg_color", "Headerbar Foreground Color");
-                headerbar_section.add_row(&headerbar_fg_color);
-        
-                let (headerbar_border_color, headerbar_border_color_button) =
-                    Self::get_color_button(&self_, "headerbar_border_color", "Headerbar Border Color");
-                headerbar_section.add_row(&headerbar_border_color);
-        
-                let (headerbar_backdrop_color, headerbar_backdrop_color_button) = Self::get_color_button(
-                    &self_,
-                    "headerbar_backdrop_color",
-                    "Headerbar Backdrop Color",
-                );
-                headerbar_section.add_row(&headerbar_backdrop_color);
-        
-                let (headerbar_shade_color, headerbar_shade_color_button) =
-                    Self::get_color_button(&self_, "headerbar_shade_color", "Shade Color");
-                headerbar_section.add_row(&headerbar_shade_color);
-        
-                let card_section = ExpanderRow::builder()
-                    .name("Card Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Card Colors")
-                    .hexpand(true)
-                    .build();
-                let (card_bg_color, card_bg_color_button) =
-                    Self::get_color_button(&self_, "card_bg_color", "Card Background Color");
-                card_section.add_row(&card_bg_color);
-                let (card_fg_color, card_fg_color_button) =
-                    Self::get_color_button(&self_, "card_fg_color", "Card Foreground Color");
-                card_section.add_row(&card_fg_color);
-                let (card_shade_color, card_shade_color_button) =
-                    Self::get_color_button(&self_, "card_shade_color", "Card Shade Color");
-                card_section.add_row(&card_shade_color);
-        
-                let popover_section = ExpanderRow::builder()
-                    .name("Popover Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Popover Colors")
-                    .hexpand(true)
-                    .build();
-                let (popover_bg_color, popover_bg_color_button) =
-                    Self::get_color_button(&self_, "popover_bg_color", "Popover Background Color");
-                popover_section.add_row(&popover_bg_color);
-                let (popover_fg_color, popover_fg_color_button) =
-                    Self::get_color_button(&self_, "popover_fg_color", "Popover Foreground Color");
-                popover_section.add_row(&popover_fg_color);
-        
-                let misc_section = ExpanderRow::builder()
-                    .name("Miscellaneous Colors")
-                    .expanded(false)
-                    .enable_expansion(true)
-                    .title("Miscellaneous Colors")
-                    .hexpand(true)
-                    .build();
-                let (scrollbar_outline_color, scrollbar_outline_color_button) =
-                    Self::get_color_button(&self_, "scrollbar_outline_color", "Scrollbar Outline Color");
-                misc_section.add_row(&scrollbar_outline_color);
-                let (shade_color, shade_color_button) =
-                    Self::get_color_button(&self_, "shade_color", "Shade Color");
-                misc_section.add_row(&shade_color);
-        
-                    color_editor.append(&accent_section);
-                    color_editor.append(&destructive_section);
-                    color_editor.append(&status_section);
-                    color_editor.append(&content_section);
-                    color_editor.append(&window_section);
-                    color_editor.append(&headerbar_section);
-                    color_editor.append(&card_section);
-                    color_editor.append(&popover_section);
-                    color_editor.append(&misc_section);
+            closure_local!(@weak-allow-none self as self_ => move |_file_button: ThemeChooserButton, f: File| {
+                if let (Some(self_), Some(Ok(t))) = (self_, f.path().as_ref().map(|p| ColorOverrides::load(p))) {
+                    self_.load_theme(t);
                 }
             }),
         );
     }
 
+    // show a theme in the editor
+    fn load_theme(&self, t: ColorOverrides) {
+        let imp = imp::ColorOverridesEditor::from_instance(self);
+        imp.name.get().unwrap().set_text(&t.name);
+        imp.theme.replace(t);
+
+        let color_editor = imp.color_editor.get().unwrap();
+        let mut c = color_editor.first_child();
+        while let Some(child) = c {
+            color_editor.remove(&child);
+            c = color_editor.first_child();
+        }
+        self.append_sections(color_editor);
+    }
+
+    // add an expander row for each group of colors
+    fn append_sections(&self, color_editor: &Box) {
+        // TODO use i18n for labels
+        for group in ColorGroup::ALL {
+            let section = ExpanderRow::builder()
+                .name(group.label())
+                .expanded(matches!(group, ColorGroup::Accent | ColorGroup::Destructive))
+                .enable_expansion(true)
+                .title(group.label())
+                .hexpand(true)
+                .build();
+            for key in group.keys() {
+                let (color_box, _) = self.get_color_button(key);
+                section.add_row(&color_box);
+            }
+            color_editor.append(&section);
+        }
+    }
+
     fn update_color_buttons(&self) {
         let imp = imp::ColorOverridesEditor::from_instance(&self);
         // TODO update alll buttons to match colors with current theme
     }
 
-    fn get_color_button(&self, id: &str, label: &str) -> (Box, ColorButton) {
+    fn get_color_button(&self, key: &ColorKey) -> (Box, ColorButton) {
         // TODO add button for clearing color
         let imp = imp::ColorOverridesEditor::from_instance(&self);
+        let (id, label) = (key.name, key.label);
 
         let rgba = SRGBA::default().into();
         let color_button = cascade! {
@@ -620,7 +498,7 @@ impl ColorOverridesEditor {
             ..set_title(label);
             ..set_use_alpha(true);
         };
-        if let Some(Ok(c)) = imp.theme.borrow().get_key(id).map(|c| RGBA::parse(&c)) {
+        if let Some(Ok(c)) = imp.theme.borrow().get_key(id).map(|c| RGBA::parse(&c.to_string())) {
             color_button.set_rgba(&c);
         } else {
             color_button.set_rgba(&RGBA::new(0.0, 0.0, 0.0, 0.0));
@@ -652,6 +530,7 @@ impl ColorOverridesEditor {
                 set_margin_start: 4,
                 set_margin_end: 4,
                 set_hexpand: true,
+                set_tooltip_text: Some(key.description),
 
                 append: &color_button,
 
@@ -703,8 +582,10 @@ impl ColorOverridesEditor {
                 } else {
                     ColorOverrides::light_default()
                 };
-                let preview_css = &mut default_theme.as_css();
-                preview_css.push_str(&theme.borrow().as_css());
+                // fall back to the unresolved theme if a base theme is missing
+                let theme = theme.borrow();
+                let theme = theme.resolve().unwrap_or_else(|_| theme.clone());
+                let preview_css = ColorOverrides::merge(&default_theme, &theme).as_css(CssMode::Symbolic);
                 css_provider.get().unwrap().load_from_data(preview_css.as_bytes());
             }),
         );
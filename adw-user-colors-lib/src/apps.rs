@@ -0,0 +1,349 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atomic,
+    color_scheme::ColorScheme,
+    colors::{ColorOverrides, CssMode},
+    config::Config,
+    gtk_css, GTK4_PREFIX, NAME,
+};
+
+/// state file listing the application stylesheets written by the service
+pub const WRITTEN_FILE: &'static str = "app-stylesheets.ron";
+
+/// themes of a single application, overriding the selected light and dark themes
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct AppThemes {
+    /// light theme of the application, the selected light theme is used if unset
+    pub light: Option<String>,
+    /// dark theme of the application, the selected dark theme is used if unset
+    pub dark: Option<String>,
+    /// stylesheet read by the application, relative paths are resolved against the home directory
+    /// the stylesheet in the application's flatpak config directory is used if unset
+    pub path: Option<PathBuf>,
+}
+
+impl AppThemes {
+    /// get the name of the theme the application overrides for the color scheme
+    pub fn theme_name(&self, color_scheme: ColorScheme) -> Option<&str> {
+        match color_scheme {
+            ColorScheme::Light => self.light.as_deref(),
+            ColorScheme::Dark => self.dark.as_deref(),
+        }
+    }
+
+    /// resolve the path of the application's stylesheet
+    pub fn path(&self, app_id: &str) -> anyhow::Result<PathBuf> {
+        match &self.path {
+            Some(p) if p.is_absolute() => Ok(p.clone()),
+            Some(p) => Ok(home_dir()?.join(p)),
+            None => flatpak_css_path(app_id),
+        }
+    }
+}
+
+/// check whether the string is a valid application ID, eg. `org.gnome.Nautilus`
+/// application IDs follow the rules of D-Bus names, so they are safe to use in paths
+pub fn is_valid_app_id(id: &str) -> bool {
+    let elements: Vec<_> = id.split('.').collect();
+    id.len() <= 255
+        && elements.len() >= 2
+        && elements.iter().all(|e| {
+            !e.is_empty()
+                && !e.starts_with(|c: char| c.is_ascii_digit())
+                && e.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        })
+}
+
+/// path of the GTK 4 stylesheet in the flatpak config directory of an application
+pub fn flatpak_css_path(app_id: &str) -> anyhow::Result<PathBuf> {
+    if !is_valid_app_id(app_id) {
+        anyhow::bail!("invalid application ID {app_id}");
+    }
+    Ok(home_dir()?
+        .join(".var/app")
+        .join(app_id)
+        .join("config")
+        .join(GTK4_PREFIX)
+        .join("gtk.css"))
+}
+
+/// application stylesheets the service wrote to, so they can be cleaned up even if the config
+/// changed since
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Written {
+    /// stylesheets containing the managed block
+    pub files: BTreeSet<PathBuf>,
+    /// directories created for the stylesheets, they are removed again if they are empty
+    pub dirs: BTreeSet<PathBuf>,
+}
+
+impl Written {
+    fn path() -> anyhow::Result<PathBuf> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+        Ok(xdg_dirs.place_state_file(WRITTEN_FILE)?)
+    }
+
+    /// load the written stylesheets, nothing was written if the state file is missing
+    pub fn load() -> anyhow::Result<Self> {
+        match fs::read_to_string(Self::path()?) {
+            Ok(s) => Ok(ron::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let ron = ron::ser::to_string_pretty(self, Default::default())?;
+        atomic::write(&Self::path()?, ron)
+    }
+
+    /// load the written stylesheets, change them and save them if anything changed
+    /// the changes are saved even if `f` fails, as it may have written some stylesheets
+    pub fn update(f: impl FnOnce(&mut Self) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let loaded = Self::load()?;
+        let mut written = loaded.clone();
+        let res = f(&mut written);
+        if written != loaded {
+            written.save()?;
+        }
+        res
+    }
+
+    /// remove the managed block from a stylesheet, and the directories created for it if they
+    /// are empty now
+    pub fn remove(&mut self, path: &Path) -> anyhow::Result<()> {
+        gtk_css::remove_block_at(path)?;
+        self.files.remove(path);
+        // children come after their parents
+        let dirs: Vec<_> = self
+            .dirs
+            .iter()
+            .rev()
+            .filter(|d| path.starts_with(d) && !self.files.iter().any(|f| f.starts_with(d)))
+            .cloned()
+            .collect();
+        for dir in dirs {
+            // directories the application added files to are left alone
+            if fs::remove_dir(&dir).is_err() && dir.exists() {
+                log::debug!("{} is not empty, keeping it", dir.display());
+            }
+            self.dirs.remove(&dir);
+        }
+        Ok(())
+    }
+
+    /// write the managed block into a stylesheet, recording the directories created for it
+    pub fn write(&mut self, path: &Path, css: &str) -> anyhow::Result<()> {
+        let created: Vec<_> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|d| !d.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        // recorded first, so partially created directories are cleaned up too
+        self.dirs.extend(created);
+        self.files.insert(path.to_path_buf());
+        gtk_css::write_block_at(path, css)
+    }
+}
+
+/// application IDs of the locally installed flatpak applications
+/// applications are found in the user and system installations, and by their config directories
+pub fn flatpak_app_ids() -> anyhow::Result<BTreeSet<String>> {
+    let base_dirs = xdg::BaseDirectories::new()?;
+    let dirs = [
+        home_dir()?.join(".var/app"),
+        base_dirs.get_data_home().join("flatpak/app"),
+        PathBuf::from("/var/lib/flatpak/app"),
+    ];
+    let mut ids = BTreeSet::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries.flatten() {
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or_default();
+            if let Some(id) = entry.file_name().to_str() {
+                if is_dir && is_valid_app_id(id) {
+                    ids.insert(id.to_string());
+                }
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// render the stylesheets of the configured applications for the color scheme
+/// if flatpak applications are themed, the desktop theme is also rendered for every installed
+/// flatpak application without its own entry
+/// returns the generated css by stylesheet path, applications without a theme or whose theme
+/// fails to load are skipped
+pub fn render_all(config: &Config, color_scheme: ColorScheme) -> BTreeMap<PathBuf, String> {
+    let mut rendered = BTreeMap::new();
+    if config.flatpak {
+        match render_flatpak(config, color_scheme) {
+            Ok(flatpak) => rendered.extend(flatpak),
+            Err(e) => log::error!("failed to render the flatpak application stylesheets: {e}"),
+        }
+    }
+    for (app_id, themes) in &config.apps {
+        let name = config.app_theme_name(app_id, color_scheme);
+        if name.is_empty() {
+            log::debug!("no theme is selected for {app_id}, skipping");
+            continue;
+        }
+        let res = themes.path(app_id).and_then(|path| {
+            let overrides = ColorOverrides::load_from_name(name)?.resolve()?;
+            Ok((path, overrides.as_css(CssMode::Symbolic)))
+        });
+        match res {
+            Ok((path, css)) => {
+                rendered.insert(path, css);
+            }
+            Err(e) => log::error!("failed to render {name} for {app_id}: {e}"),
+        }
+    }
+    rendered
+}
+
+// the desktop theme for every installed flatpak application
+fn render_flatpak(
+    config: &Config,
+    color_scheme: ColorScheme,
+) -> anyhow::Result<BTreeMap<PathBuf, String>> {
+    let name = config.theme_name(color_scheme);
+    if name.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    let css = ColorOverrides::load_from_name(name)?
+        .resolve()?
+        .as_css(CssMode::Symbolic);
+    flatpak_app_ids()?
+        .into_iter()
+        .filter(|id| !config.apps.contains_key(id))
+        .map(|id| Ok((flatpak_css_path(&id)?, css.clone())))
+        .collect()
+}
+
+/// write the rendered application stylesheets, and remove the generated colors from the
+/// stylesheets which were written before but aren't rendered anymore
+/// all stylesheets are attempted, and the first error is returned
+pub fn write_rendered(rendered: &BTreeMap<PathBuf, String>) -> anyhow::Result<()> {
+    Written::update(|written| {
+        let mut res = Ok(());
+        let removed: Vec<_> = written
+            .files
+            .iter()
+            .filter(|p| !rendered.contains_key(*p))
+            .cloned()
+            .collect();
+        for path in removed {
+            if let Err(e) = written.remove(&path) {
+                log::error!("failed to remove colors from {}: {e}", path.display());
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        for (path, css) in rendered {
+            if let Err(e) = written.write(path, css) {
+                log::error!("failed to write {}: {e}", path.display());
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    })
+}
+
+/// remove the generated colors from all application stylesheets the service wrote to
+pub fn remove_all() -> anyhow::Result<()> {
+    Written::update(|written| {
+        let mut res = Ok(());
+        for path in written.files.clone() {
+            if let Err(e) = written.remove(&path) {
+                log::error!("failed to remove colors from {}: {e}", path.display());
+                if res.is_ok() {
+                    res = Err(e);
+                }
+            }
+        }
+        res
+    })
+}
+
+fn home_dir() -> anyhow::Result<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|h| !h.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("HOME is not set"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_ids() {
+        assert!(is_valid_app_id("org.gnome.Nautilus"));
+        assert!(is_valid_app_id("com.github.wash2.adw-user-colors"));
+        assert!(!is_valid_app_id("nautilus"));
+        assert!(!is_valid_app_id("org..Nautilus"));
+        assert!(!is_valid_app_id("org.gnome.2048"));
+        assert!(!is_valid_app_id("../../.config"));
+        assert!(!is_valid_app_id("org.gnome/Nautilus"));
+
+        let themes = AppThemes {
+            light: Some("red".into()),
+            ..Default::default()
+        };
+        assert_eq!(themes.theme_name(ColorScheme::Light), Some("red"));
+        assert_eq!(themes.theme_name(ColorScheme::Dark), None);
+        assert!(themes
+            .path("org.gnome.Nautilus")
+            .unwrap()
+            .ends_with(".var/app/org.gnome.Nautilus/config/gtk-4.0/gtk.css"));
+        assert!(themes.path("../evil").is_err());
+    }
+
+    #[test]
+    fn clean_up_written() {
+        let dir = std::env::temp_dir().join(format!("adw-user-colors-apps-{}", std::process::id()));
+        let app_dir = dir.join("org.gnome.Nautilus");
+        let created = app_dir.join("config/gtk-4.0/gtk.css");
+        let existing = dir.join("org.gnome.Maps/gtk.css");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, "window {}\n").unwrap();
+
+        let mut written = Written::default();
+        written
+            .write(&created, "@define-color accent_color red;\n")
+            .unwrap();
+        written
+            .write(&existing, "@define-color accent_color red;\n")
+            .unwrap();
+        assert!(written.dirs.contains(&app_dir));
+        assert!(!written.dirs.contains(&dir));
+
+        // created directories are removed with the stylesheet, the user's css is kept
+        written.remove(&created).unwrap();
+        written.remove(&existing).unwrap();
+        assert!(!app_dir.exists());
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "window {}\n");
+        assert_eq!(written, Written::default());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
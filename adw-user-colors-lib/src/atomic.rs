@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// prefix of the temporary files written next to their destination
+pub const TEMP_PREFIX: &'static str = ".adw-user-colors-";
+/// suffix of the temporary files written next to their destination
+pub const TEMP_SUFFIX: &'static str = ".tmp";
+
+/// replace the contents of a file, so that readers see either the old or the new contents
+/// the contents are written to a temporary file in the same directory, synced, and renamed over
+/// the destination. symlinks are followed, and the permissions of an existing file are kept
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    // renaming over a symlink would replace it instead of the file it points to
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = temp_path(&path)?;
+
+    let res = (|| -> anyhow::Result<()> {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        f.write_all(contents.as_ref())?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            f.set_permissions(metadata.permissions())?;
+        }
+        f.sync_all()?;
+        fs::rename(&temp, &path)?;
+        Ok(())
+    })();
+    if let Err(e) = res {
+        let _ = fs::remove_file(&temp);
+        return Err(e.context(format!("failed to write {}", path.display())));
+    }
+
+    // make the rename itself durable
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// check whether the path is a temporary file written by [`write`]
+pub fn is_temp_file(path: &Path) -> bool {
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some(n) => n.starts_with(TEMP_PREFIX) && n.ends_with(TEMP_SUFFIX),
+        None => false,
+    }
+}
+
+fn temp_path(path: &Path) -> anyhow::Result<PathBuf> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{} is not a file path", path.display()))?;
+    Ok(path.with_file_name(format!(
+        "{TEMP_PREFIX}{}.{}.{}{TEMP_SUFFIX}",
+        name.to_string_lossy(),
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_file() {
+        let dir =
+            std::env::temp_dir().join(format!("adw-user-colors-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gtk.css");
+
+        write(&path, "old").unwrap();
+        write(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(is_temp_file(&temp_path(&path).unwrap()));
+        assert!(!is_temp_file(&path));
+
+        // nothing is left behind
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use adw::StyleManager;
+use gio::{glib, prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// gsettings schema of the GNOME interface settings
+pub const INTERFACE_SCHEMA: &'static str = "org.gnome.desktop.interface";
+/// key of the color scheme in the GNOME interface settings and the appearance portal namespace
+pub const COLOR_SCHEME_KEY: &'static str = "color-scheme";
+
+/// bus name of the XDG desktop portal
+pub const PORTAL_NAME: &'static str = "org.freedesktop.portal.Desktop";
+/// object path of the XDG desktop portal
+pub const PORTAL_PATH: &'static str = "/org/freedesktop/portal/desktop";
+/// interface of the settings portal
+pub const SETTINGS_INTERFACE: &'static str = "org.freedesktop.portal.Settings";
+/// settings portal namespace of the color scheme
+pub const APPEARANCE_NAMESPACE: &'static str = "org.freedesktop.appearance";
+
+/// whether the light or the dark theme is active
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    /// map a value of the `color-scheme` gsettings key to a color scheme
+    pub fn from_gsettings(value: &str) -> Self {
+        match value {
+            "prefer-dark" => Self::Dark,
+            _ => Self::Light,
+        }
+    }
+
+    /// the value of the `color-scheme` gsettings key selecting the color scheme
+    pub fn to_gsettings(self) -> &'static str {
+        match self {
+            Self::Light => "default",
+            Self::Dark => "prefer-dark",
+        }
+    }
+
+    /// map a value of the portal's `color-scheme` setting to a color scheme
+    /// 0 is no preference, 1 prefers dark and 2 prefers light
+    pub fn from_portal(value: u32) -> Self {
+        match value {
+            1 => Self::Dark,
+            _ => Self::Light,
+        }
+    }
+}
+
+/// where the system color scheme is read from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ColorSchemeBackend {
+    /// use the first backend which is available
+    #[default]
+    Auto,
+    /// libadwaita's style manager, requires a display
+    Adwaita,
+    /// the `color-scheme` key of the GNOME interface settings
+    GSettings,
+    /// the `color-scheme` setting of the XDG settings portal
+    Portal,
+}
+
+/// a source of the system color scheme
+pub trait ColorSchemeSource {
+    /// the current color scheme
+    fn color_scheme(&self) -> ColorScheme;
+
+    /// call `f` whenever the color scheme changes
+    /// callbacks are dispatched on the thread default main context, which must be running
+    fn connect_changed(&self, f: Box<dyn Fn(ColorScheme)>);
+}
+
+/// create the source for the backend
+/// `Auto` prefers backends which don't need a display
+pub fn source(backend: ColorSchemeBackend) -> anyhow::Result<Box<dyn ColorSchemeSource>> {
+    Ok(match backend {
+        ColorSchemeBackend::Adwaita => Box::new(AdwaitaSource::new()?),
+        ColorSchemeBackend::GSettings => Box::new(GSettingsSource::new()?),
+        ColorSchemeBackend::Portal => Box::new(PortalSource::new()?),
+        ColorSchemeBackend::Auto => match PortalSource::new() {
+            Ok(s) => Box::new(s),
+            Err(e) => {
+                log::info!("settings portal is unavailable: {e}");
+                match GSettingsSource::new() {
+                    Ok(s) => Box::new(s),
+                    Err(e) => {
+                        log::info!("{e}, falling back to libadwaita");
+                        Box::new(AdwaitaSource::new()?)
+                    }
+                }
+            }
+        },
+    })
+}
+
+/// reads the color scheme from libadwaita, initializing GTK
+pub struct AdwaitaSource {
+    manager: StyleManager,
+}
+
+impl AdwaitaSource {
+    pub fn new() -> anyhow::Result<Self> {
+        adw::gtk::init()?;
+        adw::init();
+        Ok(Self {
+            manager: StyleManager::default(),
+        })
+    }
+}
+
+impl ColorSchemeSource for AdwaitaSource {
+    fn color_scheme(&self) -> ColorScheme {
+        if self.manager.is_dark() {
+            ColorScheme::Dark
+        } else {
+            ColorScheme::Light
+        }
+    }
+
+    fn connect_changed(&self, f: Box<dyn Fn(ColorScheme)>) {
+        self.manager.connect_dark_notify(move |manager| {
+            f(if manager.is_dark() {
+                ColorScheme::Dark
+            } else {
+                ColorScheme::Light
+            })
+        });
+    }
+}
+
+/// reads the color scheme from the GNOME interface settings, without GTK
+pub struct GSettingsSource {
+    settings: gio::Settings,
+}
+
+impl GSettingsSource {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            settings: interface_settings()?,
+        })
+    }
+}
+
+/// set the system color scheme in the GNOME interface settings
+/// the settings portal forwards the change to applications
+pub fn set_system_color_scheme(color_scheme: ColorScheme) -> anyhow::Result<()> {
+    let settings = interface_settings()?;
+    if ColorScheme::from_gsettings(&settings.string(COLOR_SCHEME_KEY)) != color_scheme {
+        settings.set_string(COLOR_SCHEME_KEY, color_scheme.to_gsettings())?;
+        gio::Settings::sync();
+    }
+    Ok(())
+}
+
+fn interface_settings() -> anyhow::Result<gio::Settings> {
+    // gio aborts if the schema or key is missing, so check first
+    let schema = gio::SettingsSchemaSource::default()
+        .and_then(|s| s.lookup(INTERFACE_SCHEMA, true))
+        .filter(|s| s.has_key(COLOR_SCHEME_KEY));
+    match schema {
+        Some(schema) => Ok(gio::Settings::new_full(
+            &schema,
+            None::<&gio::SettingsBackend>,
+            None,
+        )),
+        None => anyhow::bail!("{INTERFACE_SCHEMA} has no {COLOR_SCHEME_KEY} key"),
+    }
+}
+
+impl ColorSchemeSource for GSettingsSource {
+    fn color_scheme(&self) -> ColorScheme {
+        ColorScheme::from_gsettings(&self.settings.string(COLOR_SCHEME_KEY))
+    }
+
+    fn connect_changed(&self, f: Box<dyn Fn(ColorScheme)>) {
+        self.settings
+            .connect_changed(Some(COLOR_SCHEME_KEY), move |settings, key| {
+                f(ColorScheme::from_gsettings(&settings.string(key)))
+            });
+    }
+}
+
+/// reads the color scheme from the XDG settings portal over D-Bus, without GTK
+pub struct PortalSource {
+    connection: gio::DBusConnection,
+}
+
+impl PortalSource {
+    /// connect to the portal on the session bus
+    pub fn new() -> anyhow::Result<Self> {
+        let connection = gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)?;
+        Self::with_connection(connection)
+    }
+
+    /// use the portal on the bus of the connection
+    /// fails if the portal doesn't provide the color scheme
+    pub fn with_connection(connection: gio::DBusConnection) -> anyhow::Result<Self> {
+        let source = Self { connection };
+        source.read()?;
+        Ok(source)
+    }
+
+    /// read the color scheme from the portal
+    pub fn read(&self) -> anyhow::Result<ColorScheme> {
+        let args = (APPEARANCE_NAMESPACE, COLOR_SCHEME_KEY).to_variant();
+        // `Read` is deprecated, but `ReadOne` is only available since version 2 of the interface
+        let reply = self
+            .call("ReadOne", &args)
+            .or_else(|_| self.call("Read", &args))?;
+        reply
+            .try_child_value(0)
+            .and_then(|v| portal_value(&v))
+            .map(ColorScheme::from_portal)
+            .ok_or_else(|| anyhow::anyhow!("unexpected reply from the settings portal: {reply}"))
+    }
+
+    fn call(&self, method: &str, args: &glib::Variant) -> anyhow::Result<glib::Variant> {
+        Ok(self.connection.call_sync(
+            Some(PORTAL_NAME),
+            PORTAL_PATH,
+            SETTINGS_INTERFACE,
+            method,
+            Some(args),
+            Some(glib::VariantTy::new("(v)").unwrap()),
+            gio::DBusCallFlags::NONE,
+            -1,
+            None::<&gio::Cancellable>,
+        )?)
+    }
+}
+
+impl ColorSchemeSource for PortalSource {
+    fn color_scheme(&self) -> ColorScheme {
+        self.read().unwrap_or_else(|e| {
+            log::warn!("failed to read the color scheme from the settings portal: {e}");
+            ColorScheme::Light
+        })
+    }
+
+    fn connect_changed(&self, f: Box<dyn Fn(ColorScheme)>) {
+        self.connection.signal_subscribe(
+            Some(PORTAL_NAME),
+            Some(SETTINGS_INTERFACE),
+            Some("SettingChanged"),
+            Some(PORTAL_PATH),
+            Some(APPEARANCE_NAMESPACE),
+            gio::DBusSignalFlags::NONE,
+            move |_, _, _, _, _, params| {
+                if let Some((namespace, key, value)) =
+                    params.get::<(String, String, glib::Variant)>()
+                {
+                    if namespace == APPEARANCE_NAMESPACE && key == COLOR_SCHEME_KEY {
+                        if let Some(value) = portal_value(&value) {
+                            f(ColorScheme::from_portal(value));
+                        }
+                    }
+                }
+            },
+        );
+    }
+}
+
+// portal values are variants, which `Read` wraps in another variant
+fn portal_value(v: &glib::Variant) -> Option<u32> {
+    if v.type_() == glib::VariantTy::VARIANT {
+        v.as_variant().and_then(|inner| portal_value(&inner))
+    } else {
+        v.get::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::tests::{connect, private_bus};
+
+    #[test]
+    fn gsettings_values() {
+        assert_eq!(
+            ColorScheme::from_gsettings("prefer-dark"),
+            ColorScheme::Dark
+        );
+        assert_eq!(
+            ColorScheme::from_gsettings("prefer-light"),
+            ColorScheme::Light
+        );
+        assert_eq!(ColorScheme::from_gsettings("default"), ColorScheme::Light);
+    }
+
+    const SETTINGS_XML: &'static str = r#"
+<node>
+  <interface name="org.freedesktop.portal.Settings">
+    <method name="ReadOne">
+      <arg type="s" name="namespace" direction="in"/>
+      <arg type="s" name="key" direction="in"/>
+      <arg type="v" name="value" direction="out"/>
+    </method>
+    <signal name="SettingChanged">
+      <arg type="s" name="namespace"/>
+      <arg type="s" name="key"/>
+      <arg type="v" name="value"/>
+    </signal>
+  </interface>
+</node>"#;
+
+    // a mock settings portal serving a color scheme of 1, its connection is returned to emit
+    // signals
+    fn mock_portal(address: &str) -> gio::DBusConnection {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mock_address = address.to_string();
+        std::thread::spawn(move || {
+            let context = glib::MainContext::new();
+            context
+                .with_thread_default(|| {
+                    let connection = connect(&mock_address);
+                    let info = gio::DBusNodeInfo::for_xml(SETTINGS_XML).unwrap();
+                    connection
+                        .register_object(
+                            PORTAL_PATH,
+                            &info.lookup_interface(SETTINGS_INTERFACE).unwrap(),
+                            |_, _, _, _, _, _, invocation| {
+                                let value = glib::Variant::from_variant(&1u32.to_variant());
+                                invocation.return_value(Some(&(value,).to_variant()));
+                            },
+                            |_, _, _, _, _| unreachable!(),
+                            |_, _, _, _, _, _| unreachable!(),
+                        )
+                        .unwrap();
+                    connection
+                        .call_sync(
+                            Some("org.freedesktop.DBus"),
+                            "/org/freedesktop/DBus",
+                            "org.freedesktop.DBus",
+                            "RequestName",
+                            Some(&(PORTAL_NAME, 0u32).to_variant()),
+                            None,
+                            gio::DBusCallFlags::NONE,
+                            -1,
+                            None::<&gio::Cancellable>,
+                        )
+                        .unwrap();
+                    tx.send(connection).unwrap();
+                    glib::MainLoop::new(Some(&context), false).run();
+                })
+                .unwrap();
+        });
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn portal_color_scheme() {
+        let bus = private_bus();
+        let mock = mock_portal(&bus.address);
+
+        let context = glib::MainContext::new();
+        context
+            .with_thread_default(|| {
+                let source = PortalSource::with_connection(connect(&bus.address)).unwrap();
+                assert_eq!(source.color_scheme(), ColorScheme::Dark);
+
+                let changed = std::rc::Rc::new(std::cell::Cell::new(None));
+                let changed_clone = changed.clone();
+                source.connect_changed(Box::new(move |scheme| changed_clone.set(Some(scheme))));
+                // make sure the match rule is registered before the signal is sent
+                source.read().unwrap();
+
+                let emit = |namespace: &str, value: u32| {
+                    let value = glib::Variant::from_variant(&value.to_variant());
+                    mock.emit_signal(
+                        None,
+                        PORTAL_PATH,
+                        SETTINGS_INTERFACE,
+                        "SettingChanged",
+                        Some(&(namespace, COLOR_SCHEME_KEY, value).to_variant()),
+                    )
+                    .unwrap();
+                };
+                // other namespaces are ignored
+                emit("org.gnome.desktop.interface", 1);
+                emit(APPEARANCE_NAMESPACE, 2);
+
+                let start = std::time::Instant::now();
+                while changed.get().is_none() && start.elapsed().as_secs() < 5 {
+                    context.iteration(false);
+                }
+                assert_eq!(changed.get(), Some(ColorScheme::Light));
+            })
+            .unwrap();
+    }
+}
@@ -1,79 +1,617 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
 use std::{
+    fmt,
     fs::File,
-    io::{Write, BufReader},
+    io::Read,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use serde::{Deserialize, Serialize};
+use palette::Srgba;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{NAME, THEME_DIR, config::Config};
+use crate::{
+    atomic,
+    config::Config,
+    expr::{ColorEvalError, ColorExpr},
+    library::{self, ThemeLibrary},
+    migration::{self, Migrated, THEME_VERSION},
+    NAME, THEME_DIR,
+};
+
+/// name of the built-in light theme
+pub const LIGHT_DEFAULT: &'static str = "light_default";
+/// name of the built-in dark theme
+pub const DARK_DEFAULT: &'static str = "dark_default";
+
+/// named colors used by Adwaita for GTK 3, and the libadwaita colors they are rendered from
+pub const GTK3_NAMED_COLORS: &[(&str, &str)] = &[
+    ("theme_fg_color", "window_fg_color"),
+    ("theme_text_color", "view_fg_color"),
+    ("theme_bg_color", "window_bg_color"),
+    ("theme_base_color", "view_bg_color"),
+    ("theme_selected_bg_color", "accent_bg_color"),
+    ("theme_selected_fg_color", "accent_fg_color"),
+    ("theme_unfocused_fg_color", "window_fg_color"),
+    ("theme_unfocused_text_color", "view_fg_color"),
+    ("theme_unfocused_bg_color", "window_bg_color"),
+    ("theme_unfocused_base_color", "view_bg_color"),
+    ("theme_unfocused_selected_bg_color", "accent_bg_color"),
+    ("theme_unfocused_selected_fg_color", "accent_fg_color"),
+    ("content_view_bg", "view_bg_color"),
+    ("text_view_bg", "view_bg_color"),
+];
+
+/// a color value as accepted by GTK css
+/// the text it was parsed from is kept, so it is serialized exactly as it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Color {
+    text: String,
+    expr: ColorExpr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorParseError {
+    pub text: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ColorParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid color `{}`: {}", self.text, self.reason)
+    }
+}
+
+impl std::error::Error for ColorParseError {}
+
+/// how color expressions are written by `as_css`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CssMode {
+    /// write colors as they were defined, keeping references and expressions
+    Symbolic,
+    /// evaluate colors to literals where possible
+    /// references to colors the theme doesn't define are kept
+    Flatten,
+}
+
+impl Color {
+    pub fn expr(&self) -> &ColorExpr {
+        &self.expr
+    }
+
+    /// the color as sRGBA, if it is a literal
+    pub fn rgba(&self) -> Option<Srgba> {
+        match self.expr {
+            ColorExpr::Literal(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// name of the referenced color, if it is a plain reference
+    pub fn reference(&self) -> Option<&str> {
+        match &self.expr {
+            ColorExpr::Reference(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// `#rrggbb` hex string, if the color is an opaque literal
+    pub fn to_hex(&self) -> Option<String> {
+        self.rgba().filter(|c| c.alpha >= 1.0).map(|c| {
+            let c: Srgba<u8> = c.into_format();
+            format!("#{:02x}{:02x}{:02x}", c.red, c.green, c.blue)
+        })
+    }
+}
+
+impl From<Srgba> for Color {
+    fn from(c: Srgba) -> Self {
+        let c8: Srgba<u8> = c.into_format();
+        // GTK 3 doesn't understand hex colors with alpha
+        let text = if c8.alpha == u8::MAX {
+            format!("#{:02x}{:02x}{:02x}", c8.red, c8.green, c8.blue)
+        } else {
+            format!(
+                "rgba({}, {}, {}, {})",
+                c8.red,
+                c8.green,
+                c8.blue,
+                (c.alpha * 1000.0).round() / 1000.0
+            )
+        };
+        Self {
+            text,
+            expr: ColorExpr::Literal(c),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ColorParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expr = ColorExpr::parse(s).map_err(|reason| ColorParseError {
+            text: s.to_string(),
+            reason,
+        })?;
+        Ok(Self {
+            text: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// groups of related colors, in the order they are shown to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorGroup {
+    Accent,
+    Destructive,
+    Status,
+    Content,
+    Window,
+    Headerbar,
+    Card,
+    Popover,
+    Misc,
+}
+
+impl ColorGroup {
+    pub const ALL: &'static [ColorGroup] = &[
+        ColorGroup::Accent,
+        ColorGroup::Destructive,
+        ColorGroup::Status,
+        ColorGroup::Content,
+        ColorGroup::Window,
+        ColorGroup::Headerbar,
+        ColorGroup::Card,
+        ColorGroup::Popover,
+        ColorGroup::Misc,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ColorGroup::Accent => "Accent Colors",
+            ColorGroup::Destructive => "Destructive Colors",
+            ColorGroup::Status => "Status Colors",
+            ColorGroup::Content => "Content Colors",
+            ColorGroup::Window => "Window Colors",
+            ColorGroup::Headerbar => "Headerbar Colors",
+            ColorGroup::Card => "Card Colors",
+            ColorGroup::Popover => "Popover Colors",
+            ColorGroup::Misc => "Miscellaneous Colors",
+        }
+    }
+
+    /// the colors of the group, in registry order
+    pub fn keys(&self) -> impl Iterator<Item = &'static ColorKey> {
+        let group = *self;
+        COLOR_KEYS.iter().filter(move |k| k.group == group)
+    }
+}
+
+/// a color a theme can override
+pub struct ColorKey {
+    /// name of the color in css, and of the field in the theme
+    pub name: &'static str,
+    pub group: ColorGroup,
+    /// human readable name
+    pub label: &'static str,
+    pub description: &'static str,
+    /// value in `ColorOverrides::light_default`
+    pub light_default: &'static str,
+    /// value in `ColorOverrides::dark_default`
+    pub dark_default: &'static str,
+    get: fn(&ColorOverrides) -> &Option<Color>,
+    get_mut: fn(&mut ColorOverrides) -> &mut Option<Color>,
+}
+
+impl ColorKey {
+    pub fn get<'a>(&self, t: &'a ColorOverrides) -> Option<&'a Color> {
+        (self.get)(t).as_ref()
+    }
+
+    pub fn set(&self, t: &mut ColorOverrides, value: Option<Color>) {
+        *(self.get_mut)(t) = value;
+    }
+}
+
+impl PartialEq for ColorKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for ColorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ColorKey")
+            .field("name", &self.name)
+            .field("group", &self.group)
+            .finish()
+    }
+}
+
+macro_rules! color_key {
+    ($name:ident, $group:ident, $label:literal, $description:literal, $light:literal, $dark:literal) => {
+        ColorKey {
+            name: stringify!($name),
+            group: ColorGroup::$group,
+            label: $label,
+            description: $description,
+            light_default: $light,
+            dark_default: $dark,
+            get: |t| &t.$name,
+            get_mut: |t| &mut t.$name,
+        }
+    };
+}
+
+/// every color a theme can override
+pub static COLOR_KEYS: &[ColorKey] = &[
+    color_key!(
+        accent_bg_color,
+        Accent,
+        "Accent Background Color",
+        "Background of suggested-action buttons and selected items",
+        "#1c71d8",
+        "#3584e4"
+    ),
+    color_key!(
+        accent_fg_color,
+        Accent,
+        "Accent Foreground Color",
+        "Text on top of the accent background",
+        "#3584e4",
+        "#ffffff"
+    ),
+    color_key!(
+        accent_color,
+        Accent,
+        "Accent Color",
+        "Accent used as text or icon color on regular backgrounds",
+        "#ffffff",
+        "#78aeed"
+    ),
+    color_key!(
+        destructive_bg_color,
+        Destructive,
+        "Destructive Background Color",
+        "Background of destructive-action buttons",
+        "#c01c28",
+        "#c01c28"
+    ),
+    color_key!(
+        destructive_fg_color,
+        Destructive,
+        "Destructive Foreground Color",
+        "Text on top of the destructive background",
+        "#ffffff",
+        "#ffffff"
+    ),
+    color_key!(
+        destructive_color,
+        Destructive,
+        "Destructive Color",
+        "Destructive color used as text or icon color on regular backgrounds",
+        "#c01c28",
+        "#ff7b63"
+    ),
+    color_key!(
+        success_color,
+        Status,
+        "Success Color",
+        "Success color used as text or icon color on regular backgrounds",
+        "#26a269",
+        "#8ff0a4"
+    ),
+    color_key!(
+        success_bg_color,
+        Status,
+        "Success Background Color",
+        "Background of success elements, like level bars and infobars",
+        "#2ec27e",
+        "#26a269"
+    ),
+    color_key!(
+        success_fg_color,
+        Status,
+        "Success Foreground Color",
+        "Text on top of the success background",
+        "#ffffff",
+        "#ffffff"
+    ),
+    color_key!(
+        warning_color,
+        Status,
+        "Warning Color",
+        "Warning color used as text or icon color on regular backgrounds",
+        "#ae7b03",
+        "#f8e45c"
+    ),
+    color_key!(
+        warning_bg_color,
+        Status,
+        "Warning Background Color",
+        "Background of warning elements, like level bars and infobars",
+        "#e5a50a",
+        "#cd9309"
+    ),
+    color_key!(
+        warning_fg_color,
+        Status,
+        "Warning Foreground Color",
+        "Text on top of the warning background",
+        "rgba(0, 0, 0, 0.8)",
+        "rgba(0, 0, 0, 0.8)"
+    ),
+    color_key!(
+        error_color,
+        Status,
+        "Error Color",
+        "Error color used as text or icon color on regular backgrounds",
+        "#c01c28",
+        "#ff7b63"
+    ),
+    color_key!(
+        error_bg_color,
+        Status,
+        "Error Background Color",
+        "Background of error elements, like level bars and infobars",
+        "#e01b24",
+        "#ff7b63"
+    ),
+    color_key!(
+        error_fg_color,
+        Status,
+        "Error Foreground Color",
+        "Text on top of the error background",
+        "#ffffff",
+        "#ffffff"
+    ),
+    color_key!(
+        window_bg_color,
+        Window,
+        "Window Background Color",
+        "Main window background",
+        "#fafafa",
+        "#242424"
+    ),
+    color_key!(
+        window_fg_color,
+        Window,
+        "Window Foreground Color",
+        "Text on top of the window background",
+        "rgba(0, 0, 0, 0.8)",
+        "#ffffff"
+    ),
+    color_key!(
+        view_bg_color,
+        Content,
+        "Widget Base Color",
+        "Background of content areas, like text views",
+        "#ffffff",
+        "#1e1e1e"
+    ),
+    color_key!(
+        view_fg_color,
+        Content,
+        "Widget Text Color",
+        "Text in content areas",
+        "#000000",
+        "#ffffff"
+    ),
+    color_key!(
+        headerbar_bg_color,
+        Headerbar,
+        "Headerbar Background Color",
+        "Background of header bars, search bars and tab bars",
+        "#ebebeb",
+        "#303030"
+    ),
+    color_key!(
+        headerbar_fg_color,
+        Headerbar,
+        "Headerbar Foreground Color",
+        "Text on top of header bars",
+        "rgba(0, 0, 0, 0.8)",
+        "#ffffff"
+    ),
+    color_key!(
+        headerbar_border_color,
+        Headerbar,
+        "Headerbar Border Color",
+        "Border of header bars",
+        "rgba(0, 0, 0, 0.8)",
+        "#ffffff"
+    ),
+    color_key!(
+        headerbar_backdrop_color,
+        Headerbar,
+        "Headerbar Backdrop Color",
+        "Background of header bars in unfocused windows",
+        "#fafafa",
+        "#242424"
+    ),
+    color_key!(
+        headerbar_shade_color,
+        Headerbar,
+        "Headerbar Shade Color",
+        "Shadow below header bars",
+        "rgba(0, 0, 0, 0.07)",
+        "rgba(0, 0, 0, 0.36)"
+    ),
+    color_key!(
+        card_bg_color,
+        Card,
+        "Card Background Color",
+        "Background of cards and boxed lists",
+        "#ffffff",
+        "rgba(255, 255, 255, 0.08)"
+    ),
+    color_key!(
+        card_fg_color,
+        Card,
+        "Card Foreground Color",
+        "Text on top of cards",
+        "rgba(0, 0, 0, 0.8)",
+        "#ffffff"
+    ),
+    color_key!(
+        card_shade_color,
+        Card,
+        "Card Shade Color",
+        "Shadow and border of cards",
+        "rgba(0, 0, 0, 0.07)",
+        "rgba(0, 0, 0, 0.36)"
+    ),
+    color_key!(
+        popover_bg_color,
+        Popover,
+        "Popover Background Color",
+        "Background of popovers",
+        "#ffffff",
+        "#383838"
+    ),
+    color_key!(
+        popover_fg_color,
+        Popover,
+        "Popover Foreground Color",
+        "Text on top of popovers",
+        "rgba(0, 0, 0, 0.8)",
+        "#ffffff"
+    ),
+    color_key!(
+        scrollbar_outline_color,
+        Misc,
+        "Scrollbar Outline Color",
+        "Outline of overlay scrollbars",
+        "#ffffff",
+        "#ffffff"
+    ),
+    color_key!(
+        shade_color,
+        Misc,
+        "Shade Color",
+        "Shadows of sidebars, dimmed pages and other elements",
+        "rgba(0, 0, 0, 0.07)",
+        "rgba(0, 0, 0, 0.07)"
+    ),
+];
+
+/// look up a color in the registry by name
+pub fn color_key(name: &str) -> Option<&'static ColorKey> {
+    COLOR_KEYS.iter().find(|k| k.name == name)
+}
+
+/// difference between two themes, see `ColorOverrides::diff`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ColorDiff {
+    /// colors only set by the other theme
+    pub added: Vec<(&'static ColorKey, Color)>,
+    /// colors only set by this theme
+    pub removed: Vec<(&'static ColorKey, Color)>,
+    /// colors set by both themes to different values, this theme's value first
+    pub changed: Vec<(&'static ColorKey, Color, Color)>,
+}
+
+impl ColorDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
 
 #[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
 pub struct ColorOverrides {
+    /// version of the theme file format, see `migration::THEME_VERSION`
+    pub version: u32,
     /// name
     pub name: String,
-    pub accent_bg_color: Option<String>,
-    pub accent_fg_color: Option<String>,
-    pub accent_color: Option<String>,
+    /// name of the theme this theme inherits unset colors from
+    pub base: Option<String>,
+    pub accent_bg_color: Option<Color>,
+    pub accent_fg_color: Option<Color>,
+    pub accent_color: Option<Color>,
 
     // destructive-action buttons
-    pub destructive_bg_color: Option<String>,
-    pub destructive_fg_color: Option<String>,
-    pub destructive_color: Option<String>,
+    pub destructive_bg_color: Option<Color>,
+    pub destructive_fg_color: Option<Color>,
+    pub destructive_color: Option<Color>,
 
     // Levelbars, entries, labels and infobars. These don't need text colors
-    pub success_color: Option<String>,
-    pub success_bg_color: Option<String>,
-    pub success_fg_color: Option<String>,
+    pub success_color: Option<Color>,
+    pub success_bg_color: Option<Color>,
+    pub success_fg_color: Option<Color>,
 
-    pub warning_color: Option<String>,
-    pub warning_bg_color: Option<String>,
-    pub warning_fg_color: Option<String>,
+    pub warning_color: Option<Color>,
+    pub warning_bg_color: Option<Color>,
+    pub warning_fg_color: Option<Color>,
 
-    pub error_color: Option<String>,
-    pub error_bg_color: Option<String>,
-    pub error_fg_color: Option<String>,
+    pub error_color: Option<Color>,
+    pub error_bg_color: Option<Color>,
+    pub error_fg_color: Option<Color>,
 
     // Main window background
-    pub window_bg_color: Option<String>,
-    pub window_fg_color: Option<String>,
+    pub window_bg_color: Option<Color>,
+    pub window_fg_color: Option<Color>,
 
     // Content areas, e.g. text views
-    pub view_bg_color: Option<String>,
-    pub view_fg_color: Option<String>,
+    pub view_bg_color: Option<Color>,
+    pub view_fg_color: Option<Color>,
 
     // Header bar, search bar, tab bar
-    pub headerbar_bg_color: Option<String>,
-    pub headerbar_fg_color: Option<String>,
-    pub headerbar_border_color: Option<String>,
-    pub headerbar_backdrop_color: Option<String>,
-    pub headerbar_shade_color: Option<String>,
+    pub headerbar_bg_color: Option<Color>,
+    pub headerbar_fg_color: Option<Color>,
+    pub headerbar_border_color: Option<Color>,
+    pub headerbar_backdrop_color: Option<Color>,
+    pub headerbar_shade_color: Option<Color>,
 
     // Cards, boxed lists
-    pub card_bg_color: Option<String>,
-    pub card_fg_color: Option<String>,
-    pub card_shade_color: Option<String>,
+    pub card_bg_color: Option<Color>,
+    pub card_fg_color: Option<Color>,
+    pub card_shade_color: Option<Color>,
 
     // Popovers
-    pub popover_bg_color: Option<String>,
-    pub popover_fg_color: Option<String>,
+    pub popover_bg_color: Option<Color>,
+    pub popover_fg_color: Option<Color>,
 
     // Miscellaneous
-    pub scrollbar_outline_color: Option<String>,
-    pub shade_color: Option<String>,
+    pub scrollbar_outline_color: Option<Color>,
+    pub shade_color: Option<Color>,
 }
 
 impl ColorOverrides {
+    /// write the theme to the user's theme directory, names which aren't plain file names are
+    /// refused
     pub fn save(&self) -> anyhow::Result<()> {
+        if !library::is_valid_name(&self.name) {
+            anyhow::bail!("invalid theme name {:?}", self.name);
+        }
         let ron_path: PathBuf = [NAME, THEME_DIR].iter().collect();
         let ron_dirs = xdg::BaseDirectories::with_prefix(ron_path)?;
         let ron_name = format!("{}.ron", &self.name);
 
         if let Ok(p) = ron_dirs.place_data_file(ron_name) {
-            let mut f = File::create(p)?;
-            f.write_all(ron::ser::to_string(self)?.as_bytes())?;
+            atomic::write(&p, self.to_ron()?)?;
         } else {
             anyhow::bail!("Failed to write RON theme.");
         }
@@ -86,349 +624,341 @@ impl ColorOverrides {
         Ok(base_dirs.create_data_directory(ron_path)?)
     }
 
-    pub fn load_from_name(name: &str) -> anyhow::Result<Self> {
-        let ron_path: PathBuf = [NAME, THEME_DIR].iter().collect();
-        let ron_dirs = xdg::BaseDirectories::with_prefix(ron_path)?;
+    /// path of the theme file with the given name, if there is one
+    /// see [`ThemeLibrary`] for the order the theme directories are searched in
+    pub fn find_path(name: &str) -> anyhow::Result<Option<PathBuf>> {
+        Ok(ThemeLibrary::new()?.find(name)?.and_then(|e| e.path))
+    }
 
-        let ron_name = format!("{}.ron", name);
-        if let Some(p) = ron_dirs.find_data_file(ron_name) {
-            let f = File::open(p)?;
-            Ok(ron::de::from_reader(f)?)
+    pub fn load_from_name(name: &str) -> anyhow::Result<Self> {
+        if let Some(p) = Self::find_path(name)? {
+            Self::load(&p)
+        } else if let Some(t) = Self::builtin(name) {
+            Ok(t)
         } else {
-            anyhow::bail!("Failed to write RON theme.");
+            anyhow::bail!("Could not find theme {name}");
         }
     }
 
+    /// names of the themes in the theme directories and the built-in themes, sorted
+    pub fn list_names() -> anyhow::Result<Vec<String>> {
+        Ok(ThemeLibrary::new()?
+            .list()?
+            .into_iter()
+            .map(|e| e.name)
+            .collect())
+    }
+
+    /// the themes shipped with the library
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            LIGHT_DEFAULT => Some(Self::light_default()),
+            DARK_DEFAULT => Some(Self::dark_default()),
+            _ => None,
+        }
+    }
+
+    /// flatten the chain of base themes into a single theme without a base
+    pub fn resolve(&self) -> anyhow::Result<Self> {
+        self.resolve_with(&mut |name| Self::load_from_name(name))
+    }
+
+    /// flatten the chain of base themes, loading base themes by name with `load`
+    pub fn resolve_with(
+        &self,
+        load: &mut dyn FnMut(&str) -> anyhow::Result<Self>,
+    ) -> anyhow::Result<Self> {
+        let mut chain = vec![self.clone()];
+        let mut names = vec![self.name.clone()];
+        while let Some(base) = chain.last().and_then(|t| t.base.clone()) {
+            if names.contains(&base) {
+                names.push(base);
+                anyhow::bail!("theme inheritance cycle: {}", names.join(" -> "));
+            }
+            let t = load(&base)
+                .map_err(|e| anyhow::anyhow!("failed to load base theme {base}: {e}"))?;
+            names.push(base);
+            chain.push(t);
+        }
+
+        let mut resolved = Self::default();
+        for t in chain.iter().rev() {
+            resolved = Self::merge(&resolved, t);
+        }
+        resolved.base = None;
+        if let Some(cycle) = resolved.find_cycle() {
+            anyhow::bail!("{}", ColorEvalError::Cycle(cycle));
+        }
+        Ok(resolved)
+    }
+
     pub fn load(p: &dyn AsRef<Path>) -> anyhow::Result<Self> {
-        let f = File::open(p)?;
-        Ok(ron::de::from_reader(f)?)
+        let (t, migrated) = Self::read(p.as_ref())?;
+        for w in &migrated.warnings {
+            log::warn!("{}: {w}", p.as_ref().display());
+        }
+        Ok(t)
     }
 
+    /// upgrade a theme file on disk to the current version
+    /// returns the warnings found while upgrading, the file is left alone if it is up to date
+    pub fn upgrade(p: &dyn AsRef<Path>) -> anyhow::Result<Vec<String>> {
+        let (t, migrated) = Self::read(p.as_ref())?;
+        if migrated.is_outdated() {
+            atomic::write(p.as_ref(), t.to_ron()?)?;
+            log::info!(
+                "upgraded {} from version {} to {THEME_VERSION}",
+                p.as_ref().display(),
+                migrated.from_version
+            );
+        }
+        Ok(migrated.warnings)
+    }
+
+    fn read(p: &Path) -> anyhow::Result<(Self, Migrated)> {
+        let mut f = File::open(p)?;
+        let mut s = String::new();
+        f.read_to_string(&mut s)?;
+        Self::from_ron_migrated(&s).map_err(|e| anyhow::anyhow!("{}: {e}", p.display()))
+    }
+
+    /// parse a RON theme, pointing at the offending key if a color is invalid
+    /// themes written by older versions are upgraded, and problems are logged as warnings
+    pub fn from_ron(s: &str) -> anyhow::Result<Self> {
+        let (t, migrated) = Self::from_ron_migrated(s)?;
+        for w in &migrated.warnings {
+            log::warn!("{}: {w}", t.name);
+        }
+        Ok(t)
+    }
+
+    /// parse a RON theme of any version, returning what was changed to upgrade it
+    pub fn from_ron_migrated(s: &str) -> anyhow::Result<(Self, Migrated)> {
+        let migrated = migration::migrate(ron::de::from_str(s)?)?;
+        let t: Self = match migrated.value.clone().into_rust() {
+            Ok(t) => t,
+            Err(e) => match invalid_key(s) {
+                Some((key, color_err)) => anyhow::bail!("invalid value for `{key}`: {color_err}"),
+                None => return Err(e.into()),
+            },
+        };
+        if let Some(cycle) = t.find_cycle() {
+            anyhow::bail!("{}", ColorEvalError::Cycle(cycle));
+        }
+        Ok((t, migrated))
+    }
+
+    /// serialize the theme in the current file format
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let t = Self {
+            version: self.version.max(THEME_VERSION),
+            ..self.clone()
+        };
+        Ok(ron::ser::to_string(&t)?)
+    }
+
+    /// load the active theme, including the colors inherited from its base themes
     pub fn load_active() -> anyhow::Result<Self> {
         let config = Config::load()?;
         let active = match config.active_name() {
             Some(n) => n,
             _ => anyhow::bail!("No configured active overrides")
         };
-        let css_path: PathBuf = [NAME, THEME_DIR].iter().collect();
-        let css_dirs = xdg::BaseDirectories::with_prefix(css_path)?;
-        let active_theme_path = match css_dirs.find_data_file(format!("{active}.ron")) {
+        let active_theme_path = match Self::find_path(&active)? {
             Some(p) => p,
             _ => anyhow::bail!("Could not find theme")
         };
-        let active_theme_file = File::open(active_theme_path)?;
-        let reader = BufReader::new(active_theme_file);
-        Ok(ron::de::from_reader(reader)?)
+        Self::load(&active_theme_path)?.resolve()
     }
 
     pub fn light_default() -> Self {
-        ron::de::from_bytes(include_bytes!("light_default.ron")).unwrap()
+        Self::from_defaults(LIGHT_DEFAULT, |k| k.light_default)
     }
 
     pub fn dark_default() -> Self {
-        ron::de::from_bytes(include_bytes!("dark_default.ron")).unwrap()
+        Self::from_defaults(DARK_DEFAULT, |k| k.dark_default)
+    }
+
+    fn from_defaults(name: &str, default: fn(&ColorKey) -> &'static str) -> Self {
+        let mut t = Self {
+            version: THEME_VERSION,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        for key in COLOR_KEYS {
+            key.set(&mut t, Some(default(key).parse().unwrap()));
+        }
+        t
     }
 
     pub fn set_key(&mut self, key: &str, value: Option<String>) -> anyhow::Result<()> {
-        match key {
-            "accent_bg_color" => self.accent_bg_color = value,
-            "accent_fg_color" => self.accent_fg_color = value,
-            "accent_color" => self.accent_color = value,
-
-            // destructive-action buttons
-            "destructive_bg_color" => self.destructive_bg_color = value,
-            "destructive_fg_color" => self.destructive_fg_color = value,
-            "destructive_color" => self.destructive_color = value,
-
-            "success_color" => self.success_color = value,
-            "success_bg_color" => self.success_color = value,
-            "success_fg_color" => self.success_color = value,
-
-            "warning_color" => self.warning_color = value,
-            "warning_bg_color" => self.warning_color = value,
-            "warning_fg_color" => self.warning_color = value,
-
-            "error_color" => self.error_color = value,
-            "error_bg_color" => self.error_color = value,
-            "error_fg_color" => self.error_color = value,
-
-            // Content areas, e.g. text views
-            "view_bg_color" => self.view_bg_color = value,
-            "view_fg_color" => self.view_fg_color = value,
-
-            // Main window background
-            "window_bg_color" => self.window_bg_color = value,
-            "window_fg_color" => self.window_fg_color = value,
-
-            // Header bar, search bar, tab bar
-            "headerbar_bg_color" => self.headerbar_bg_color = value,
-            "headerbar_fg_color" => self.headerbar_fg_color = value,
-            "headerbar_border_color" => self.headerbar_border_color = value,
-            "headerbar_backdrop_color" => self.headerbar_backdrop_color = value,
-            "headerbar_shade_color" => self.headerbar_shade_color = value,
-
-            // Cards, boxed lists
-            "card_bg_color" => self.card_bg_color = value,
-            "card_fg_color" => self.card_fg_color = value,
-            "card_shade_color" => self.card_shade_color = value,
-
-            // Popovers
-            "popover_bg_color" => self.popover_bg_color = value,
-            "popover_fg_color" => self.popover_fg_color = value,
-
-            // Miscellaneous
-            "scrollbar_outline_color" => self.scrollbar_outline_color = value,
-            "shade_color" => self.shade_color = value,
-            _ => anyhow::bail!("Invalid key"),
+        let value = value
+            .map(|v| v.parse::<Color>())
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("invalid value for `{key}`: {e}"))?;
+        let old = self.get_key(key);
+        self.set_color(key, value)?;
+        if let Some(cycle) = self.find_cycle() {
+            self.set_color(key, old)?;
+            anyhow::bail!("invalid value for `{key}`: {}", ColorEvalError::Cycle(cycle));
         }
         Ok(())
     }
 
-    pub fn get_key(&self, key: &str) -> Option<String> {
-        match key {
-            "accent_bg_color" => self.accent_bg_color.clone(),
-            "accent_fg_color" => self.accent_fg_color.clone(),
-            "accent_color" => self.accent_color.clone(),
-
-            // destructive-action buttons
-            "destructive_bg_color" => self.destructive_bg_color.clone(),
-            "destructive_fg_color" => self.destructive_fg_color.clone(),
-            "destructive_color" => self.destructive_color.clone(),
-
-            "success_color" => self.success_color.clone(),
-            "success_bg_color" => self.success_color.clone(),
-            "success_fg_color" => self.success_color.clone(),
-
-            "warning_color" => self.warning_color.clone(),
-            "warning_bg_color" => self.warning_color.clone(),
-            "warning_fg_color" => self.warning_color.clone(),
-
-            "error_color" => self.error_color.clone(),
-            "error_bg_color" => self.error_color.clone(),
-            "error_fg_color" => self.error_color.clone(),
-
-            // Content areas.clone(), e.g. text views
-            "view_bg_color" => self.view_bg_color.clone(),
-            "view_fg_color" => self.view_fg_color.clone(),
-
-            // Main window background
-            "window_bg_color" => self.window_bg_color.clone(),
-            "window_fg_color" => self.window_fg_color.clone(),
-
-            // Header bar.clone(), search bar.clone(), tab bar
-            "headerbar_bg_color" => self.headerbar_bg_color.clone(),
-            "headerbar_fg_color" => self.headerbar_fg_color.clone(),
-            "headerbar_border_color" => self.headerbar_border_color.clone(),
-            "headerbar_backdrop_color" => self.headerbar_backdrop_color.clone(),
-            "headerbar_shade_color" => self.headerbar_shade_color.clone(),
-
-            // Cards.clone(), boxed lists
-            "card_bg_color" => self.card_bg_color.clone(),
-            "card_fg_color" => self.card_fg_color.clone(),
-            "card_shade_color" => self.card_shade_color.clone(),
-
-            // Popovers
-            "popover_bg_color" => self.popover_bg_color.clone(),
-            "popover_fg_color" => self.popover_fg_color.clone(),
-
-            // Miscellaneous
-            "scrollbar_outline_color" => self.scrollbar_outline_color.clone(),
-            "shade_color" => self.shade_color.clone(),
-            _ => None,
+    fn set_color(&mut self, key: &str, value: Option<Color>) -> anyhow::Result<()> {
+        match color_key(key) {
+            Some(k) => k.set(self, value),
+            None => anyhow::bail!("Invalid key"),
+        }
+        Ok(())
+    }
+
+    pub fn get_key(&self, key: &str) -> Option<Color> {
+        color_key(key).and_then(|k| k.get(self)).cloned()
+    }
+
+    /// the colors the theme sets, in registry order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static ColorKey, &Color)> {
+        COLOR_KEYS.iter().filter_map(move |k| k.get(self).map(|c| (k, c)))
+    }
+
+    /// compare the colors of the theme with another theme
+    /// colors are compared by value, so `#FFF` and `white` are the same
+    pub fn diff(&self, other: &Self) -> ColorDiff {
+        let mut diff = ColorDiff::default();
+        for key in COLOR_KEYS {
+            match (key.get(self), key.get(other)) {
+                (None, Some(b)) => diff.added.push((key, b.clone())),
+                (Some(a), None) => diff.removed.push((key, a.clone())),
+                (Some(a), Some(b)) if a.expr() != b.expr() => {
+                    diff.changed.push((key, a.clone(), b.clone()))
+                }
+                _ => {}
+            }
         }
+        diff
     }
 
-    pub fn as_css(&self) -> String {
+    /// combine two themes, colors set by `overlay` replace the ones set by `base`
+    /// the merged theme takes the name of `overlay`
+    pub fn merge(base: &Self, overlay: &Self) -> Self {
+        let mut merged = base.clone();
+        merged.name = overlay.name.clone();
+        for (key, c) in overlay.iter() {
+            key.set(&mut merged, Some(c.clone()));
+        }
+        merged
+    }
+
+    /// evaluate a color of the theme to a literal, following references to other colors
+    pub fn evaluate(&self, key: &str) -> Result<Srgba, ColorEvalError> {
+        self.evaluate_inner(key, &mut Vec::new())
+    }
+
+    fn evaluate_inner(&self, key: &str, stack: &mut Vec<String>) -> Result<Srgba, ColorEvalError> {
+        if let Some(i) = stack.iter().position(|k| k == key) {
+            let mut cycle = stack[i..].to_vec();
+            cycle.push(key.to_string());
+            return Err(ColorEvalError::Cycle(cycle));
+        }
+        let color = self
+            .get_key(key)
+            .ok_or_else(|| ColorEvalError::Undefined(key.to_string()))?;
+        stack.push(key.to_string());
+        let res = color
+            .expr()
+            .evaluate(&mut |name| self.evaluate_inner(name, stack));
+        stack.pop();
+        res
+    }
+
+    /// find colors of the theme which reference each other
+    /// the first name of the cycle is repeated at the end
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        fn visit(
+            t: &ColorOverrides,
+            key: &str,
+            stack: &mut Vec<String>,
+            done: &mut Vec<String>,
+        ) -> Option<Vec<String>> {
+            if let Some(i) = stack.iter().position(|k| k == key) {
+                let mut cycle = stack[i..].to_vec();
+                cycle.push(key.to_string());
+                return Some(cycle);
+            }
+            if done.iter().any(|k| k == key) {
+                return None;
+            }
+            let color = t.get_key(key)?;
+            stack.push(key.to_string());
+            for name in color.expr().references() {
+                if let Some(cycle) = visit(t, name, stack, done) {
+                    return Some(cycle);
+                }
+            }
+            stack.pop();
+            done.push(key.to_string());
+            None
+        }
+
+        let mut done = Vec::new();
+        COLOR_KEYS
+            .iter()
+            .find_map(|key| visit(self, key.name, &mut Vec::new(), &mut done))
+    }
+
+    /// copy of the theme with every color evaluated to a literal where possible
+    /// colors referencing colors the theme doesn't define are kept as they are
+    pub fn flatten(&self) -> Self {
+        let mut flat = self.clone();
+        for key in COLOR_KEYS {
+            if let Ok(c) = self.evaluate(key.name) {
+                key.set(&mut flat, Some(Color::from(c)));
+            }
+        }
+        flat
+    }
+
+    pub fn as_css(&self, mode: CssMode) -> String {
+        if mode == CssMode::Flatten {
+            return self.flatten().as_css(CssMode::Symbolic);
+        }
         let mut user_color_css = String::new();
-        if let Some(accent_bg_color) = self.accent_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color accent_bg_color {};\n",
-                &accent_bg_color
-            ));
-        }
-        if let Some(accent_fg_color) = self.accent_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color accent_fg_color {};\n",
-                &accent_fg_color
-            ));
-        }
-        if let Some(accent_color) = self.accent_color.as_ref() {
-            user_color_css.push_str(&format!("@define-color accent_color {};\n", &accent_color));
-        }
-
-        if let Some(destructive_bg_color) = self.destructive_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color destructive_bg_color {};\n",
-                &destructive_bg_color
-            ));
-        }
-        if let Some(destructive_fg_color) = self.destructive_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color destructive_fg_color {};\n",
-                &destructive_fg_color
-            ));
-        }
-        if let Some(destructive_color) = self.destructive_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color destructive_color {};\n",
-                &destructive_color
-            ));
-        }
-
-        if let Some(success_color) = self.success_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color success_color {};\n",
-                &success_color
-            ));
-        }
-        if let Some(success_bg_color) = self.success_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color success_bg_color {};\n",
-                &success_bg_color
-            ));
-        }
-        if let Some(success_fg_color) = self.success_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color success_fg_color {};\n",
-                &success_fg_color
-            ));
-        }
-        if let Some(warning_color) = self.warning_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color warning_color {};\n",
-                &warning_color
-            ));
-        }
-        if let Some(warning_bg_color) = self.warning_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color warning_bg_color {};\n",
-                &warning_bg_color
-            ));
-        }
-        if let Some(warning_fg_color) = self.warning_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color warning_fg_color {};\n",
-                &warning_fg_color
-            ));
-        }
-        if let Some(error_color) = self.error_color.as_ref() {
-            user_color_css.push_str(&format!("@define-color error_color {};\n", &error_color));
-        }
-        if let Some(error_bg_color) = self.error_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color error_bg_color {};\n",
-                &error_bg_color
-            ));
-        }
-        if let Some(error_fg_color) = self.error_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color error_fg_color {};\n",
-                &error_fg_color
-            ));
-        }
-
-        if let Some(window_bg_color) = self.window_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color window_bg_color {};\n",
-                &window_bg_color
-            ));
-        }
-        if let Some(window_fg_color) = self.window_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color window_fg_color {};\n",
-                &window_fg_color
-            ));
-        }
-
-        if let Some(view_bg_color) = self.view_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color view_bg_color {};\n",
-                &view_bg_color
-            ));
-        }
-        if let Some(view_fg_color) = self.view_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color view_fg_color {};\n",
-                &view_fg_color
-            ));
-        }
-        if let Some(shade_color) = self.shade_color.as_ref() {
-            user_color_css.push_str(&format!("@define-color shade_color {};\n", &shade_color));
-        }
-
-        if let Some(headerbar_bg_color) = self.headerbar_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color headerbar_bg_color {};\n",
-                &headerbar_bg_color
-            ));
-        }
-        if let Some(headerbar_fg_color) = self.headerbar_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color headerbar_fg_color {};\n",
-                &headerbar_fg_color
-            ));
-        }
-        if let Some(headerbar_border_color) = self.headerbar_border_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color headerbar_border_color {};\n",
-                &headerbar_border_color
-            ));
-        }
-        if let Some(headerbar_backdrop_color) = self.headerbar_backdrop_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color headerbar_backdrop_color {};\n",
-                &headerbar_backdrop_color
-            ));
-        }
-        if let Some(headerbar_shade_color) = self.headerbar_shade_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color headerbar_shade_color {};\n",
-                &headerbar_shade_color
-            ));
-        }
-
-        if let Some(card_bg_color) = self.card_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color card_bg_color {};\n",
-                &card_bg_color
-            ));
-        }
-        if let Some(card_fg_color) = self.card_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color card_fg_color {};\n",
-                &card_fg_color
-            ));
-        }
-        if let Some(card_shade_color) = self.card_shade_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color card_shade_color {};\n",
-                &card_shade_color
-            ));
-        }
-
-        if let Some(popover_bg_color) = self.popover_bg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color popover_bg_color {};\n",
-                &popover_bg_color
-            ));
-        }
-        if let Some(popover_fg_color) = self.popover_fg_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color popover_fg_color {};\n",
-                &popover_fg_color
-            ));
-        }
-
-        if let Some(scrollbar_outline_color) = self.scrollbar_outline_color.as_ref() {
-            user_color_css.push_str(&format!(
-                "@define-color scrollbar_outline_color {};\n",
-                &scrollbar_outline_color
-            ));
+        for (key, c) in self.iter() {
+            user_color_css.push_str(&format!("@define-color {} {c};\n", key.name));
+        }
+        user_color_css
+    }
+
+    /// css for GTK 3 applications
+    /// the libadwaita names are kept for themes like adw-gtk3 which use them directly
+    pub fn as_gtk3_css(&self) -> String {
+        let mut user_color_css = self.as_css(CssMode::Symbolic);
+        for (gtk3_name, key) in GTK3_NAMED_COLORS {
+            if let Some(c) = self.get_key(key) {
+                user_color_css.push_str(&format!("@define-color {gtk3_name} {c};\n"));
+            }
         }
         user_color_css
     }
 }
 
+// find the first key holding an invalid color
+fn invalid_key(s: &str) -> Option<(String, ColorParseError)> {
+    let map = match ron::de::from_str(s).ok()? {
+        ron::Value::Map(map) => map,
+        _ => return None,
+    };
+    let invalid = map.iter().find_map(|(k, v)| match (k, v) {
+        (ron::Value::String(k), ron::Value::Option(Some(v))) => match v.as_ref() {
+            ron::Value::String(v) => v.parse::<Color>().err().map(|e| (k.clone(), e)),
+            _ => None,
+        },
+        _ => None,
+    });
+    invalid
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -440,4 +970,202 @@ mod tests {
     fn dark_default() {
         super::ColorOverrides::dark_default();
     }
+
+    #[test]
+    fn registry_round_trip() {
+        use super::{color_key, ColorGroup, ColorOverrides, COLOR_KEYS};
+
+        let mut t = ColorOverrides::default();
+        for (i, key) in COLOR_KEYS.iter().enumerate() {
+            assert_eq!(color_key(key.name).unwrap().name, key.name);
+            assert!(ColorGroup::ALL.contains(&key.group));
+            let value = format!("#{:06x}", i + 1);
+            t.set_key(key.name, Some(value.clone())).unwrap();
+            assert_eq!(t.get_key(key.name).unwrap().to_string(), value, "{}", key.name);
+        }
+        for (i, key) in COLOR_KEYS.iter().enumerate() {
+            assert_eq!(t.get_key(key.name).unwrap().to_string(), format!("#{:06x}", i + 1));
+        }
+
+        let css = t.as_css(super::CssMode::Symbolic);
+        assert_eq!(css.lines().count(), COLOR_KEYS.len());
+        let ron = ron::ser::to_string(&t).unwrap();
+        let parsed = ColorOverrides::from_ron(&ron).unwrap();
+        assert_eq!(parsed.as_css(super::CssMode::Symbolic), css);
+
+        for key in COLOR_KEYS {
+            t.set_key(key.name, None).unwrap();
+            assert!(t.get_key(key.name).is_none());
+        }
+        assert!(t.set_key("not_a_color", None).is_err());
+    }
+
+    #[test]
+    fn diff_and_merge() {
+        use super::ColorOverrides;
+
+        let light = ColorOverrides::light_default();
+        assert!(light.diff(&light).is_empty());
+
+        let mut user = ColorOverrides {
+            name: "user".into(),
+            ..Default::default()
+        };
+        user.set_key("accent_bg_color", Some("#e01b24".into())).unwrap();
+        // same value as the default, written differently
+        user.set_key("view_bg_color", Some("white".into())).unwrap();
+        assert_eq!(user.iter().count(), 2);
+
+        let diff = light.diff(&user);
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed.len(), super::COLOR_KEYS.len() - 2);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0.name, "accent_bg_color");
+        assert_eq!(diff.changed[0].2.to_string(), "#e01b24");
+
+        let merged = ColorOverrides::merge(&light, &user);
+        assert_eq!(merged.name, "user");
+        assert_eq!(merged.iter().count(), super::COLOR_KEYS.len());
+        assert_eq!(merged.get_key("accent_bg_color").unwrap().to_string(), "#e01b24");
+        assert_eq!(merged.get_key("view_bg_color").unwrap().to_string(), "white");
+        let diff = light.diff(&merged);
+        assert_eq!(diff.changed.len(), 1);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+    }
+
+    #[test]
+    fn resolve_base_chain() {
+        use super::ColorOverrides;
+
+        let theme = |name: &str, base: Option<&str>, accent: Option<&str>| {
+            let mut t = ColorOverrides {
+                name: name.into(),
+                base: base.map(String::from),
+                ..Default::default()
+            };
+            t.set_key("accent_bg_color", accent.map(String::from)).unwrap();
+            t
+        };
+        let mut load = |name: &str| match name {
+            "accents" => Ok(theme("accents", Some("light_default"), Some("#e01b24"))),
+            "loop_a" => Ok(theme("loop_a", Some("loop_b"), None)),
+            "loop_b" => Ok(theme("loop_b", Some("loop_a"), None)),
+            _ => ColorOverrides::builtin(name).ok_or_else(|| anyhow::anyhow!("missing")),
+        };
+
+        let user = theme("user", Some("accents"), None);
+        let resolved = user.resolve_with(&mut load).unwrap();
+        assert_eq!(resolved.name, "user");
+        assert_eq!(resolved.base, None);
+        assert_eq!(resolved.get_key("accent_bg_color").unwrap().to_string(), "#e01b24");
+        assert_eq!(resolved.get_key("window_bg_color").unwrap().to_string(), "#fafafa");
+
+        let err = theme("loop_a", Some("loop_b"), None)
+            .resolve_with(&mut load)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cycle"), "{err}");
+        assert!(theme("user", Some("missing"), None).resolve_with(&mut load).is_err());
+    }
+
+    #[test]
+    fn parse_colors() {
+        use super::Color;
+        use crate::expr::ColorExpr;
+        use palette::Srgba;
+
+        for text in ["#1c71d8", "#FFF", "rgba(0, 0, 0, 0.8)", "rgb(255,255,255)", "White", "@accent_bg_color"] {
+            let c: Color = text.parse().unwrap();
+            assert_eq!(c.to_string(), text);
+        }
+        let c: Color = "#fff".parse().unwrap();
+        assert_eq!(c.expr(), &ColorExpr::Literal(Srgba::new(1.0, 1.0, 1.0, 1.0)));
+        let c: Color = "rgba(0, 0, 0, 0.8)".parse().unwrap();
+        assert_eq!(c.rgba(), Some(Srgba::new(0.0, 0.0, 0.0, 0.8)));
+        assert_eq!(c.to_hex(), None);
+        let c: Color = "white".parse().unwrap();
+        assert_eq!(c.to_hex().as_deref(), Some("#ffffff"));
+        let c: Color = "@accent_bg_color".parse().unwrap();
+        assert_eq!(c.reference(), Some("accent_bg_color"));
+
+        for text in ["#12345", "rgb(1, 2)", "rgb(a, b, c)", "notacolor", "@", ""] {
+            assert!(text.parse::<Color>().is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn invalid_key_is_reported() {
+        let err = super::ColorOverrides::from_ron(r##"(name:"broken",accent_color:Some("#1c71d8"),view_bg_color:Some("#ggg"))"##)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("view_bg_color"), "{err}");
+
+        let mut t = super::ColorOverrides::default();
+        assert!(t.set_key("accent_color", Some("rgb(1, 2)".into())).is_err());
+        assert!(t.set_key("accent_color", Some("#1c71d8".into())).is_ok());
+    }
+
+    #[test]
+    fn migrate_old_themes() {
+        use super::ColorOverrides;
+        use crate::migration::THEME_VERSION;
+
+        // written before themes were versioned
+        let (t, migrated) = ColorOverrides::from_ron_migrated(
+            r##"(name:"old",accent_color:Some("#1c71d8"),sidebar_bg_color:Some("#ffffff"),view_bg_color:"#fafafa")"##,
+        )
+        .unwrap();
+        assert_eq!(migrated.from_version, 0);
+        assert!(migrated.is_outdated());
+        assert_eq!(migrated.warnings.len(), 1);
+        assert!(migrated.warnings[0].contains("sidebar_bg_color"));
+        assert_eq!(t.version, THEME_VERSION);
+        assert_eq!(t.get_key("view_bg_color").unwrap().to_string(), "#fafafa");
+        assert!(t.base.is_none());
+
+        let (t2, migrated) = ColorOverrides::from_ron_migrated(&t.to_ron().unwrap()).unwrap();
+        assert!(!migrated.is_outdated());
+        assert!(t.diff(&t2).is_empty());
+
+        // newer files are read as well as possible
+        let (_, migrated) =
+            ColorOverrides::from_ron_migrated(r##"(version:99,name:"new")"##).unwrap();
+        assert!(!migrated.is_outdated());
+        assert_eq!(migrated.warnings.len(), 1);
+    }
+
+    #[test]
+    fn flatten_and_cycles() {
+        use super::{ColorOverrides, CssMode};
+
+        let mut t = ColorOverrides::default();
+        t.set_key("window_fg_color", Some("#000000".into())).unwrap();
+        t.set_key("view_fg_color", Some("alpha(@window_fg_color, .5)".into()))
+            .unwrap();
+        t.set_key("card_fg_color", Some("@popover_fg_color".into()))
+            .unwrap();
+
+        let symbolic = t.as_css(CssMode::Symbolic);
+        assert!(symbolic.contains("@define-color view_fg_color alpha(@window_fg_color, .5);\n"));
+        let flat = t.as_css(CssMode::Flatten);
+        assert!(flat.contains("@define-color view_fg_color rgba(0, 0, 0, 0.5);\n"));
+        // popover_fg_color isn't defined by the theme
+        assert!(flat.contains("@define-color card_fg_color @popover_fg_color;\n"));
+
+        assert!(t.set_key("window_fg_color", Some("shade(@view_fg_color, 1.1)".into())).is_err());
+        assert_eq!(t.get_key("window_fg_color").unwrap().to_string(), "#000000");
+
+        let err = ColorOverrides::from_ron(r##"(name:"cycle",accent_color:Some("@accent_bg_color"),accent_bg_color:Some("@accent_color"))"##)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cycle"), "{err}");
+    }
+
+    #[test]
+    fn gtk3_css() {
+        let css = super::ColorOverrides::light_default().as_gtk3_css();
+        assert!(css.contains("@define-color window_bg_color #fafafa;\n"));
+        assert!(css.contains("@define-color theme_bg_color #fafafa;\n"));
+        assert!(css.contains("@define-color theme_selected_bg_color #1c71d8;\n"));
+    }
 }
\ No newline at end of file
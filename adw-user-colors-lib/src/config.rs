@@ -1,18 +1,54 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
-use crate::{colors::ColorOverrides, NAME};
-use adw::StyleManager;
+use crate::{
+    apps::{self, AppThemes},
+    atomic,
+    color_scheme::{self, ColorScheme, ColorSchemeBackend},
+    colors::ColorOverrides,
+    output::Output,
+    schedule::Schedule,
+    NAME,
+};
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::prelude::*, path::PathBuf};
+use std::{collections::BTreeMap, fs::File, io::prelude::*, path::PathBuf};
 
 /// Cosmic Theme config
-#[derive(Default, Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     /// Selected light theme name
     pub light: String,
     /// Selected dark theme name
     pub dark: String,
+    /// Where the system color scheme is read from
+    #[serde(default)]
+    pub color_scheme_backend: ColorSchemeBackend,
+    /// Also render the desktop theme into the config directories of all installed flatpak
+    /// applications, which can't read the user's gtk.css
+    #[serde(default)]
+    pub flatpak: bool,
+    /// Times to switch between the light and dark themes, instead of following the system
+    pub schedule: Option<Schedule>,
+    /// Themes of single applications by application ID, rendered into their own stylesheets
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub apps: BTreeMap<String, AppThemes>,
+    /// Destinations the active theme is rendered to
+    #[serde(default = "Output::defaults")]
+    pub outputs: Vec<Output>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            light: Default::default(),
+            dark: Default::default(),
+            color_scheme_backend: Default::default(),
+            flatpak: false,
+            schedule: None,
+            apps: BTreeMap::new(),
+            outputs: Output::defaults(),
+        }
+    }
 }
 
 pub const CONFIG_NAME: &'static str = "config.ron";
@@ -20,17 +56,27 @@ pub const CONFIG_NAME: &'static str = "config.ron";
 impl Config {
     /// create a new cosmic theme config
     pub fn new(light: String, dark: String) -> Self {
-        Self { light, dark }
+        Self {
+            light,
+            dark,
+            ..Default::default()
+        }
+    }
+
+    /// path of the config file
+    pub fn path() -> Result<PathBuf> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+        Ok(xdg_dirs
+            .get_config_home()
+            .join(format!("{CONFIG_NAME}.toml")))
     }
 
     /// save the cosmic theme config
     pub fn save(&self) -> Result<()> {
         let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
         if let Ok(path) = xdg_dirs.place_config_file(PathBuf::from(format!("{CONFIG_NAME}.toml"))) {
-            let mut f = File::create(path)?;
             let toml = toml::ser::to_string_pretty(&self)?;
-            f.write_all(toml.as_bytes())?;
-            Ok(())
+            atomic::write(&path, toml)
         } else {
             bail!("failed to save theme config")
         }
@@ -62,20 +108,78 @@ impl Config {
         }
     }
 
-    /// get the name of the active theme
+    /// get the name of the active theme, using the schedule or the configured color scheme backend
     pub fn active_name(&self) -> Option<String> {
-        if !adw::is_initialized() {
-            None
-        } else {
-            let manager = StyleManager::default();
-            if manager.is_dark() {
-                Some(self.dark.clone())
-            } else {
-                Some(self.light.clone())
+        match self.color_scheme() {
+            Ok(color_scheme) => Some(self.theme_name(color_scheme).to_string()),
+            Err(e) => {
+                log::warn!("failed to read the color scheme: {e}");
+                None
             }
         }
     }
 
+    /// get the color scheme selecting the active theme
+    /// the schedule takes precedence over the configured color scheme backend
+    pub fn color_scheme(&self) -> Result<ColorScheme> {
+        match &self.schedule {
+            Some(schedule) => Ok(schedule.color_scheme()),
+            None => Ok(color_scheme::source(self.color_scheme_backend)?.color_scheme()),
+        }
+    }
+
+    /// get the name of the theme selected for the color scheme
+    pub fn theme_name(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::Light => &self.light,
+            ColorScheme::Dark => &self.dark,
+        }
+    }
+
+    /// get the name of the theme an application uses for the color scheme
+    /// applications without an override use the theme selected for the whole desktop
+    pub fn app_theme_name(&self, app_id: &str, color_scheme: ColorScheme) -> &str {
+        self.apps
+            .get(app_id)
+            .and_then(|t| t.theme_name(color_scheme))
+            .unwrap_or_else(|| self.theme_name(color_scheme))
+    }
+
+    /// set the themes of an application, or remove its overrides
+    pub fn set_app(app_id: &str, themes: Option<AppThemes>) -> Result<()> {
+        if !apps::is_valid_app_id(app_id) {
+            bail!("invalid application ID {app_id}");
+        }
+        let mut self_ = Self::load()?;
+        match themes {
+            Some(themes) => self_.apps.insert(app_id.to_string(), themes),
+            None => self_.apps.remove(app_id),
+        };
+        self_.save()
+    }
+
+    /// replace the references to a theme, eg. after it was renamed, or remove them without a
+    /// new name
+    /// returns whether anything was replaced
+    pub fn replace_theme(&mut self, name: &str, new_name: Option<&str>) -> bool {
+        let mut replaced = false;
+        for selected in [&mut self.light, &mut self.dark] {
+            if selected == name {
+                *selected = new_name.unwrap_or_default().to_string();
+                replaced = true;
+            }
+        }
+        for themes in self.apps.values_mut() {
+            for selected in [&mut themes.light, &mut themes.dark] {
+                if selected.as_deref() == Some(name) {
+                    *selected = new_name.map(String::from);
+                    replaced = true;
+                }
+            }
+        }
+        replaced
+    }
+
     pub fn set_active_light(new: &str) -> Result<()> {
         let mut self_ = Self::load()?;
         self_.light = new.to_string();
@@ -91,18 +195,12 @@ impl Config {
 
 impl From<(ColorOverrides, ColorOverrides)> for Config {
     fn from((light, dark): (ColorOverrides, ColorOverrides)) -> Self {
-        Self {
-            light: light.name,
-            dark: dark.name,
-        }
+        Self::new(light.name, dark.name)
     }
 }
 
 impl From<ColorOverrides> for Config {
     fn from(t: ColorOverrides) -> Self {
-        Self {
-            light: t.clone().name,
-            dark: t.name,
-        }
+        Self::new(t.clone().name, t.name)
     }
 }
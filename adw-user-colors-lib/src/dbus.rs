@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::sync::{Arc, Mutex};
+
+use gio::{glib, prelude::*};
+
+use crate::{config::Config, library::ThemeLibrary};
+
+/// well-known session bus name of the running service
+pub const BUS_NAME: &'static str = "gay.ash.AdwaitaUserColors";
+/// object path of the control interface
+pub const OBJECT_PATH: &'static str = "/gay/ash/AdwaitaUserColors";
+/// name of the control interface
+pub const INTERFACE: &'static str = "gay.ash.AdwaitaUserColors";
+/// D-Bus error returned when a method fails
+pub const ERROR_FAILED: &'static str = "gay.ash.AdwaitaUserColors.Error.Failed";
+
+/// introspection data of the control interface
+pub const INTERFACE_XML: &'static str = r#"
+<node>
+  <interface name="gay.ash.AdwaitaUserColors">
+    <method name="SetActive">
+      <arg type="s" name="light" direction="in"/>
+      <arg type="s" name="dark" direction="in"/>
+    </method>
+    <method name="Reload"/>
+    <method name="ListThemes">
+      <arg type="as" name="themes" direction="out"/>
+    </method>
+    <method name="GetActiveCss">
+      <arg type="s" name="css" direction="out"/>
+    </method>
+    <signal name="ThemeApplied">
+      <arg type="s" name="name"/>
+    </signal>
+  </interface>
+</node>"#;
+
+/// what a client asked the service to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// the active themes changed
+    UpdateColors,
+    /// re-render even if nothing changed
+    Reload,
+}
+
+/// the theme most recently rendered by the service
+#[derive(Debug, Clone, Default)]
+pub struct AppliedTheme {
+    pub name: String,
+    pub css: String,
+}
+
+/// the control interface of a running service, exported on a bus connection
+pub struct ControlInterface {
+    connection: gio::DBusConnection,
+    applied: Arc<Mutex<AppliedTheme>>,
+    registration: Option<gio::RegistrationId>,
+    owner: Option<gio::OwnerId>,
+}
+
+impl ControlInterface {
+    /// export the interface on the connection and request the well-known name
+    /// themes are listed and checked in `library`
+    /// `request` is called whenever a client changes the active themes or asks for a reload
+    pub fn export(
+        connection: gio::DBusConnection,
+        library: ThemeLibrary,
+        request: impl Fn(Request) + Send + Sync + 'static,
+    ) -> anyhow::Result<Self> {
+        let info = gio::DBusNodeInfo::for_xml(INTERFACE_XML)?;
+        let interface = info
+            .lookup_interface(INTERFACE)
+            .ok_or_else(|| anyhow::anyhow!("missing interface {INTERFACE}"))?;
+        let applied = Arc::new(Mutex::new(AppliedTheme::default()));
+
+        let applied_clone = applied.clone();
+        let registration = connection.register_object(
+            OBJECT_PATH,
+            &interface,
+            move |_, _, _, _, method, params, invocation| match handle_method(
+                method,
+                &params,
+                &library,
+                &applied_clone,
+                &request,
+            ) {
+                Ok(ret) => invocation.return_value(ret.as_ref()),
+                Err(e) => invocation.return_dbus_error(ERROR_FAILED, &e.to_string()),
+            },
+            |_, _, _, _, _| unreachable!("the interface has no properties"),
+            |_, _, _, _, _, _| unreachable!("the interface has no properties"),
+        )?;
+        let owner = gio::bus_own_name_on_connection(
+            &connection,
+            BUS_NAME,
+            gio::BusNameOwnerFlags::NONE,
+            |_, name| log::info!("acquired {name}"),
+            |_, name| log::warn!("could not acquire {name}, is another instance running?"),
+        );
+
+        Ok(Self {
+            connection,
+            applied,
+            registration: Some(registration),
+            owner: Some(owner),
+        })
+    }
+
+    /// the theme most recently rendered by the service
+    pub fn applied(&self) -> AppliedTheme {
+        self.applied.lock().unwrap().clone()
+    }
+
+    /// record a newly rendered theme and emit `ThemeApplied`
+    pub fn set_applied(&self, name: &str, css: String) {
+        *self.applied.lock().unwrap() = AppliedTheme {
+            name: name.to_string(),
+            css,
+        };
+        if let Err(e) = self.connection.emit_signal(
+            None,
+            OBJECT_PATH,
+            INTERFACE,
+            "ThemeApplied",
+            Some(&(name,).to_variant()),
+        ) {
+            log::warn!("failed to emit ThemeApplied: {e}");
+        }
+    }
+}
+
+/// call a method of the running service's control interface on the session bus
+pub fn call(method: &str, args: Option<&glib::Variant>) -> anyhow::Result<glib::Variant> {
+    let connection = gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)?;
+    Ok(connection.call_sync(
+        Some(BUS_NAME),
+        OBJECT_PATH,
+        INTERFACE,
+        method,
+        args,
+        None,
+        gio::DBusCallFlags::NO_AUTO_START,
+        -1,
+        None::<&gio::Cancellable>,
+    )?)
+}
+
+impl Drop for ControlInterface {
+    fn drop(&mut self) {
+        if let Some(owner) = self.owner.take() {
+            gio::bus_unown_name(owner);
+        }
+        if let Some(registration) = self.registration.take() {
+            let _ = self.connection.unregister_object(registration);
+        }
+    }
+}
+
+fn handle_method(
+    method: &str,
+    params: &glib::Variant,
+    library: &ThemeLibrary,
+    applied: &Mutex<AppliedTheme>,
+    request: &dyn Fn(Request),
+) -> anyhow::Result<Option<glib::Variant>> {
+    match method {
+        "SetActive" => {
+            let (light, dark) = params
+                .get::<(String, String)>()
+                .ok_or_else(|| anyhow::anyhow!("expected two theme names"))?;
+            // refuse themes the service would fail to render
+            for name in [&light, &dark] {
+                library
+                    .load(name)?
+                    .resolve_with(&mut |base| library.load(base))?;
+            }
+            let mut config = Config::load()?;
+            config.light = light;
+            config.dark = dark;
+            config.save()?;
+            request(Request::UpdateColors);
+            Ok(None)
+        }
+        "Reload" => {
+            request(Request::Reload);
+            Ok(None)
+        }
+        "ListThemes" => {
+            let names: Vec<String> = library.list()?.into_iter().map(|e| e.name).collect();
+            Ok(Some((names,).to_variant()))
+        }
+        "GetActiveCss" => Ok(Some((applied.lock().unwrap().css.clone(),).to_variant())),
+        _ => anyhow::bail!("unknown method {method}"),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    const BUS_CONFIG: &'static str = r#"<busconfig>
+  <type>session</type>
+  <listen>unix:tmpdir=/tmp</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>"#;
+
+    /// a message bus daemon private to a test, stopped when dropped
+    pub(crate) struct PrivateBus {
+        pub(crate) address: String,
+        daemon: Child,
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    /// start a private bus, tests using it are ignored by default as they need dbus-daemon
+    pub(crate) fn private_bus() -> PrivateBus {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let config = std::env::temp_dir().join(format!(
+            "adw-user-colors-test-{}-{}.conf",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::write(&config, BUS_CONFIG).unwrap();
+        let daemon = Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn();
+        let mut daemon = daemon.expect("failed to start dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        // the config is read before the address is printed
+        let _ = std::fs::remove_file(&config);
+        PrivateBus {
+            address: address.trim().to_string(),
+            daemon,
+        }
+    }
+
+    pub(crate) fn connect(address: &str) -> gio::DBusConnection {
+        gio::DBusConnection::for_address_sync(
+            address,
+            gio::DBusConnectionFlags::AUTHENTICATION_CLIENT
+                | gio::DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+            None,
+            None::<&gio::Cancellable>,
+        )
+        .unwrap()
+    }
+
+    fn call(connection: &gio::DBusConnection, method: &str) -> Result<glib::Variant, glib::Error> {
+        connection.call_sync(
+            Some(BUS_NAME),
+            OBJECT_PATH,
+            INTERFACE,
+            method,
+            None,
+            None,
+            gio::DBusCallFlags::NONE,
+            1000,
+            None::<&gio::Cancellable>,
+        )
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn control_interface() {
+        // only the themes of the test are listed, not the ones of the user running it
+        let dir = std::env::temp_dir().join(format!("adw-user-colors-dbus-{}", std::process::id()));
+        let library = ThemeLibrary {
+            user_dir: dir.join("user"),
+            system_dirs: vec![dir.join("system")],
+        };
+        std::fs::create_dir_all(&library.user_dir).unwrap();
+        std::fs::write(library.user_dir.join("test.ron"), "(name: \"test\")").unwrap();
+
+        let bus = private_bus();
+
+        let (reload_tx, reload_rx) = std::sync::mpsc::channel();
+        let (loop_tx, loop_rx) = std::sync::mpsc::channel();
+        let address = bus.address.clone();
+        let service = std::thread::spawn(move || {
+            let context = glib::MainContext::new();
+            context
+                .with_thread_default(|| {
+                    let reload_tx = Mutex::new(reload_tx);
+                    let control =
+                        ControlInterface::export(connect(&address), library, move |request| {
+                            reload_tx.lock().unwrap().send(request).unwrap();
+                        })
+                        .unwrap();
+                    control.set_applied("test", "@define-color accent_color #e01b24;\n".into());
+                    let main_loop = glib::MainLoop::new(Some(&context), false);
+                    loop_tx.send(main_loop.clone()).unwrap();
+                    main_loop.run();
+                })
+                .unwrap();
+        });
+
+        let client = connect(&bus.address);
+        // the name is acquired asynchronously
+        let start = Instant::now();
+        let css = loop {
+            match call(&client, "GetActiveCss") {
+                Ok(css) => break css,
+                Err(_) if start.elapsed() < Duration::from_secs(5) => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => panic!("{e}"),
+            }
+        };
+        assert_eq!(
+            css.get::<(String,)>().unwrap().0,
+            "@define-color accent_color #e01b24;\n"
+        );
+
+        let themes = call(&client, "ListThemes").unwrap();
+        let (themes,) = themes.get::<(Vec<String>,)>().unwrap();
+        assert_eq!(
+            themes,
+            [
+                crate::colors::DARK_DEFAULT,
+                crate::colors::LIGHT_DEFAULT,
+                "test"
+            ]
+        );
+
+        call(&client, "Reload").unwrap();
+        assert_eq!(
+            reload_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Request::Reload
+        );
+
+        assert!(call(&client, "Unknown").is_err());
+
+        // the interface has to be gone before the bus, gio can't report a lost name without a
+        // connection
+        loop_rx.recv().unwrap().quit();
+        service.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
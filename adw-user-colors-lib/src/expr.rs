@@ -0,0 +1,506 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::fmt;
+
+use palette::{Hsl, IntoColor, Srgb, Srgba};
+
+/// a GTK css color expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColorExpr {
+    /// hex, `rgb()`, `rgba()` or named color
+    Literal(Srgba),
+    /// reference to another defined color, eg. `@accent_bg_color`
+    Reference(String),
+    /// `mix(color1, color2, factor)`
+    Mix(Box<ColorExpr>, Box<ColorExpr>, f32),
+    /// `shade(color, factor)`
+    Shade(Box<ColorExpr>, f32),
+    /// `alpha(color, factor)`
+    Alpha(Box<ColorExpr>, f32),
+    /// `lighter(color)`
+    Lighter(Box<ColorExpr>),
+    /// `darker(color)`
+    Darker(Box<ColorExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorEvalError {
+    /// the referenced color is not defined
+    Undefined(String),
+    /// the colors reference each other, the first name is repeated at the end
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for ColorEvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Undefined(name) => write!(f, "@{name} is not defined"),
+            Self::Cycle(names) => write!(f, "reference cycle: @{}", names.join(" -> @")),
+        }
+    }
+}
+
+impl std::error::Error for ColorEvalError {}
+
+impl ColorExpr {
+    /// parse a color expression, returning a description of the problem on failure
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut parser = Parser { s, pos: 0 };
+        let expr = parser.expr()?;
+        parser.ws();
+        if parser.pos != s.len() {
+            return Err(format!("unexpected `{}`", &s[parser.pos..]));
+        }
+        Ok(expr)
+    }
+
+    /// names of all colors referenced by the expression
+    pub fn references(&self) -> Vec<&str> {
+        match self {
+            Self::Literal(_) => Vec::new(),
+            Self::Reference(name) => vec![name.as_str()],
+            Self::Mix(a, b, _) => {
+                let mut refs = a.references();
+                refs.extend(b.references());
+                refs
+            }
+            Self::Shade(c, _) | Self::Alpha(c, _) | Self::Lighter(c) | Self::Darker(c) => {
+                c.references()
+            }
+        }
+    }
+
+    /// evaluate the expression to a literal color, looking up references with `lookup`
+    pub fn evaluate(
+        &self,
+        lookup: &mut dyn FnMut(&str) -> Result<Srgba, ColorEvalError>,
+    ) -> Result<Srgba, ColorEvalError> {
+        Ok(match self {
+            Self::Literal(c) => *c,
+            Self::Reference(name) => lookup(name)?,
+            Self::Mix(a, b, factor) => mix(a.evaluate(lookup)?, b.evaluate(lookup)?, *factor),
+            Self::Shade(c, factor) => shade(c.evaluate(lookup)?, *factor),
+            Self::Alpha(c, factor) => {
+                let mut c = c.evaluate(lookup)?;
+                c.alpha = (c.alpha * factor).clamp(0.0, 1.0);
+                c
+            }
+            Self::Lighter(c) => shade(c.evaluate(lookup)?, 1.3),
+            Self::Darker(c) => shade(c.evaluate(lookup)?, 0.7),
+        })
+    }
+}
+
+// mix premultiplied colors like GTK 4 does
+fn mix(a: Srgba, b: Srgba, factor: f32) -> Srgba {
+    let alpha = (a.alpha + (b.alpha - a.alpha) * factor).clamp(0.0, 1.0);
+    if alpha <= 0.0 {
+        return Srgba::new(0.0, 0.0, 0.0, 0.0);
+    }
+    let channel = |a_c: f32, a_a: f32, b_c: f32, b_a: f32| {
+        ((a_c * a_a + (b_c * b_a - a_c * a_a) * factor) / alpha).clamp(0.0, 1.0)
+    };
+    Srgba::new(
+        channel(a.red, a.alpha, b.red, b.alpha),
+        channel(a.green, a.alpha, b.green, b.alpha),
+        channel(a.blue, a.alpha, b.blue, b.alpha),
+        alpha,
+    )
+}
+
+// scale lightness and saturation like GTK does
+fn shade(c: Srgba, factor: f32) -> Srgba {
+    let mut hsl: Hsl = Srgb::new(c.red, c.green, c.blue).into_color();
+    hsl.lightness = (hsl.lightness * factor).clamp(0.0, 1.0);
+    hsl.saturation = (hsl.saturation * factor).clamp(0.0, 1.0);
+    let rgb: Srgb = hsl.into_color();
+    Srgba::new(rgb.red, rgb.green, rgb.blue, c.alpha)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.ws();
+        if self.rest().starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else if self.rest().is_empty() {
+            Err(format!("expected `{c}`, found the end"))
+        } else {
+            Err(format!("expected `{c}` at `{}`", self.rest()))
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !f(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn ident(&mut self) -> &'a str {
+        self.ws();
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    // a number, and whether it was a percentage
+    fn number(&mut self) -> Result<(f32, bool), String> {
+        self.ws();
+        let text =
+            self.take_while(|c| c.is_ascii_digit() || c == '.' || c == '-' || c == '+' || c == 'e');
+        let n = text
+            .parse::<f32>()
+            .map_err(|_| format!("expected a number at `{}{}`", text, self.rest()))?;
+        Ok((n, self.eat('%')))
+    }
+
+    fn factor(&mut self) -> Result<f32, String> {
+        let (n, percent) = self.number()?;
+        Ok(if percent { n / 100.0 } else { n })
+    }
+
+    fn expr(&mut self) -> Result<ColorExpr, String> {
+        if self.eat('@') {
+            let name = self.ident();
+            if name.is_empty() {
+                return Err("expected a color name after `@`".into());
+            }
+            return Ok(ColorExpr::Reference(name.to_string()));
+        }
+        if self.eat('#') {
+            let hex = self.take_while(|c| c.is_ascii_alphanumeric());
+            return parse_hex(hex)
+                .map(ColorExpr::Literal)
+                .ok_or_else(|| "expected 3, 4, 6 or 8 hex digits after `#`".into());
+        }
+
+        let name = self.ident();
+        if name.is_empty() {
+            return Err(if self.rest().is_empty() {
+                "expected a color".into()
+            } else {
+                format!("expected a color at `{}`", self.rest())
+            });
+        }
+        if !self.eat('(') {
+            return named_color(name)
+                .map(ColorExpr::Literal)
+                .ok_or_else(|| format!("unknown color name `{name}`"));
+        }
+
+        let expr = match name.to_ascii_lowercase().as_str() {
+            "rgb" | "rgba" => ColorExpr::Literal(self.rgb_args()?),
+            "mix" => {
+                let a = self.expr()?;
+                self.expect(',')?;
+                let b = self.expr()?;
+                self.expect(',')?;
+                ColorExpr::Mix(Box::new(a), Box::new(b), self.factor()?)
+            }
+            "shade" => {
+                let c = self.expr()?;
+                self.expect(',')?;
+                ColorExpr::Shade(Box::new(c), self.factor()?)
+            }
+            "alpha" => {
+                let c = self.expr()?;
+                self.expect(',')?;
+                ColorExpr::Alpha(Box::new(c), self.factor()?)
+            }
+            "lighter" => ColorExpr::Lighter(Box::new(self.expr()?)),
+            "darker" => ColorExpr::Darker(Box::new(self.expr()?)),
+            _ => return Err(format!("unknown function `{name}()`")),
+        };
+        self.expect(')')?;
+        Ok(expr)
+    }
+
+    fn rgb_args(&mut self) -> Result<Srgba, String> {
+        let mut channels = Vec::new();
+        loop {
+            let (n, percent) = self.number()?;
+            channels.push((n, percent));
+            if !self.eat(',') {
+                break;
+            }
+        }
+        if channels.len() != 3 && channels.len() != 4 {
+            return Err(format!("expected 3 or 4 arguments, found {}", channels.len()));
+        }
+        let channel = |(n, percent): (f32, bool)| {
+            if percent {
+                (n / 100.0).clamp(0.0, 1.0)
+            } else {
+                (n / 255.0).clamp(0.0, 1.0)
+            }
+        };
+        let alpha = channels
+            .get(3)
+            .map(|(n, percent)| if *percent { n / 100.0 } else { *n })
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        Ok(Srgba::new(
+            channel(channels[0]),
+            channel(channels[1]),
+            channel(channels[2]),
+            alpha,
+        ))
+    }
+}
+
+fn parse_hex(hex: &str) -> Option<Srgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u8> = match hex.len() {
+        3 | 4 => hex
+            .chars()
+            .map(|c| u8::from_str_radix(&format!("{c}{c}"), 16).ok())
+            .collect::<Option<_>>()?,
+        6 | 8 => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    let alpha = digits.get(3).copied().unwrap_or(u8::MAX);
+    Some(Srgba::<u8>::new(digits[0], digits[1], digits[2], alpha).into_format())
+}
+
+fn named_color(name: &str) -> Option<Srgba> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(Srgba::new(0.0, 0.0, 0.0, 0.0));
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, [r, g, b])| Srgba::<u8>::new(*r, *g, *b, u8::MAX).into_format())
+}
+
+/// css named colors
+pub const NAMED_COLORS: &[(&str, [u8; 3])] = &[
+    ("aliceblue", [0xf0, 0xf8, 0xff]),
+    ("antiquewhite", [0xfa, 0xeb, 0xd7]),
+    ("aqua", [0x00, 0xff, 0xff]),
+    ("aquamarine", [0x7f, 0xff, 0xd4]),
+    ("azure", [0xf0, 0xff, 0xff]),
+    ("beige", [0xf5, 0xf5, 0xdc]),
+    ("bisque", [0xff, 0xe4, 0xc4]),
+    ("black", [0x00, 0x00, 0x00]),
+    ("blanchedalmond", [0xff, 0xeb, 0xcd]),
+    ("blue", [0x00, 0x00, 0xff]),
+    ("blueviolet", [0x8a, 0x2b, 0xe2]),
+    ("brown", [0xa5, 0x2a, 0x2a]),
+    ("burlywood", [0xde, 0xb8, 0x87]),
+    ("cadetblue", [0x5f, 0x9e, 0xa0]),
+    ("chartreuse", [0x7f, 0xff, 0x00]),
+    ("chocolate", [0xd2, 0x69, 0x1e]),
+    ("coral", [0xff, 0x7f, 0x50]),
+    ("cornflowerblue", [0x64, 0x95, 0xed]),
+    ("cornsilk", [0xff, 0xf8, 0xdc]),
+    ("crimson", [0xdc, 0x14, 0x3c]),
+    ("cyan", [0x00, 0xff, 0xff]),
+    ("darkblue", [0x00, 0x00, 0x8b]),
+    ("darkcyan", [0x00, 0x8b, 0x8b]),
+    ("darkgoldenrod", [0xb8, 0x86, 0x0b]),
+    ("darkgray", [0xa9, 0xa9, 0xa9]),
+    ("darkgreen", [0x00, 0x64, 0x00]),
+    ("darkgrey", [0xa9, 0xa9, 0xa9]),
+    ("darkkhaki", [0xbd, 0xb7, 0x6b]),
+    ("darkmagenta", [0x8b, 0x00, 0x8b]),
+    ("darkolivegreen", [0x55, 0x6b, 0x2f]),
+    ("darkorange", [0xff, 0x8c, 0x00]),
+    ("darkorchid", [0x99, 0x32, 0xcc]),
+    ("darkred", [0x8b, 0x00, 0x00]),
+    ("darksalmon", [0xe9, 0x96, 0x7a]),
+    ("darkseagreen", [0x8f, 0xbc, 0x8f]),
+    ("darkslateblue", [0x48, 0x3d, 0x8b]),
+    ("darkslategray", [0x2f, 0x4f, 0x4f]),
+    ("darkslategrey", [0x2f, 0x4f, 0x4f]),
+    ("darkturquoise", [0x00, 0xce, 0xd1]),
+    ("darkviolet", [0x94, 0x00, 0xd3]),
+    ("deeppink", [0xff, 0x14, 0x93]),
+    ("deepskyblue", [0x00, 0xbf, 0xff]),
+    ("dimgray", [0x69, 0x69, 0x69]),
+    ("dimgrey", [0x69, 0x69, 0x69]),
+    ("dodgerblue", [0x1e, 0x90, 0xff]),
+    ("firebrick", [0xb2, 0x22, 0x22]),
+    ("floralwhite", [0xff, 0xfa, 0xf0]),
+    ("forestgreen", [0x22, 0x8b, 0x22]),
+    ("fuchsia", [0xff, 0x00, 0xff]),
+    ("gainsboro", [0xdc, 0xdc, 0xdc]),
+    ("ghostwhite", [0xf8, 0xf8, 0xff]),
+    ("gold", [0xff, 0xd7, 0x00]),
+    ("goldenrod", [0xda, 0xa5, 0x20]),
+    ("gray", [0x80, 0x80, 0x80]),
+    ("green", [0x00, 0x80, 0x00]),
+    ("greenyellow", [0xad, 0xff, 0x2f]),
+    ("grey", [0x80, 0x80, 0x80]),
+    ("honeydew", [0xf0, 0xff, 0xf0]),
+    ("hotpink", [0xff, 0x69, 0xb4]),
+    ("indianred", [0xcd, 0x5c, 0x5c]),
+    ("indigo", [0x4b, 0x00, 0x82]),
+    ("ivory", [0xff, 0xff, 0xf0]),
+    ("khaki", [0xf0, 0xe6, 0x8c]),
+    ("lavender", [0xe6, 0xe6, 0xfa]),
+    ("lavenderblush", [0xff, 0xf0, 0xf5]),
+    ("lawngreen", [0x7c, 0xfc, 0x00]),
+    ("lemonchiffon", [0xff, 0xfa, 0xcd]),
+    ("lightblue", [0xad, 0xd8, 0xe6]),
+    ("lightcoral", [0xf0, 0x80, 0x80]),
+    ("lightcyan", [0xe0, 0xff, 0xff]),
+    ("lightgoldenrodyellow", [0xfa, 0xfa, 0xd2]),
+    ("lightgray", [0xd3, 0xd3, 0xd3]),
+    ("lightgreen", [0x90, 0xee, 0x90]),
+    ("lightgrey", [0xd3, 0xd3, 0xd3]),
+    ("lightpink", [0xff, 0xb6, 0xc1]),
+    ("lightsalmon", [0xff, 0xa0, 0x7a]),
+    ("lightseagreen", [0x20, 0xb2, 0xaa]),
+    ("lightskyblue", [0x87, 0xce, 0xfa]),
+    ("lightslategray", [0x77, 0x88, 0x99]),
+    ("lightslategrey", [0x77, 0x88, 0x99]),
+    ("lightsteelblue", [0xb0, 0xc4, 0xde]),
+    ("lightyellow", [0xff, 0xff, 0xe0]),
+    ("lime", [0x00, 0xff, 0x00]),
+    ("limegreen", [0x32, 0xcd, 0x32]),
+    ("linen", [0xfa, 0xf0, 0xe6]),
+    ("magenta", [0xff, 0x00, 0xff]),
+    ("maroon", [0x80, 0x00, 0x00]),
+    ("mediumaquamarine", [0x66, 0xcd, 0xaa]),
+    ("mediumblue", [0x00, 0x00, 0xcd]),
+    ("mediumorchid", [0xba, 0x55, 0xd3]),
+    ("mediumpurple", [0x93, 0x70, 0xdb]),
+    ("mediumseagreen", [0x3c, 0xb3, 0x71]),
+    ("mediumslateblue", [0x7b, 0x68, 0xee]),
+    ("mediumspringgreen", [0x00, 0xfa, 0x9a]),
+    ("mediumturquoise", [0x48, 0xd1, 0xcc]),
+    ("mediumvioletred", [0xc7, 0x15, 0x85]),
+    ("midnightblue", [0x19, 0x19, 0x70]),
+    ("mintcream", [0xf5, 0xff, 0xfa]),
+    ("mistyrose", [0xff, 0xe4, 0xe1]),
+    ("moccasin", [0xff, 0xe4, 0xb5]),
+    ("navajowhite", [0xff, 0xde, 0xad]),
+    ("navy", [0x00, 0x00, 0x80]),
+    ("oldlace", [0xfd, 0xf5, 0xe6]),
+    ("olive", [0x80, 0x80, 0x00]),
+    ("olivedrab", [0x6b, 0x8e, 0x23]),
+    ("orange", [0xff, 0xa5, 0x00]),
+    ("orangered", [0xff, 0x45, 0x00]),
+    ("orchid", [0xda, 0x70, 0xd6]),
+    ("palegoldenrod", [0xee, 0xe8, 0xaa]),
+    ("palegreen", [0x98, 0xfb, 0x98]),
+    ("paleturquoise", [0xaf, 0xee, 0xee]),
+    ("palevioletred", [0xdb, 0x70, 0x93]),
+    ("papayawhip", [0xff, 0xef, 0xd5]),
+    ("peachpuff", [0xff, 0xda, 0xb9]),
+    ("peru", [0xcd, 0x85, 0x3f]),
+    ("pink", [0xff, 0xc0, 0xcb]),
+    ("plum", [0xdd, 0xa0, 0xdd]),
+    ("powderblue", [0xb0, 0xe0, 0xe6]),
+    ("purple", [0x80, 0x00, 0x80]),
+    ("rebeccapurple", [0x66, 0x33, 0x99]),
+    ("red", [0xff, 0x00, 0x00]),
+    ("rosybrown", [0xbc, 0x8f, 0x8f]),
+    ("royalblue", [0x41, 0x69, 0xe1]),
+    ("saddlebrown", [0x8b, 0x45, 0x13]),
+    ("salmon", [0xfa, 0x80, 0x72]),
+    ("sandybrown", [0xf4, 0xa4, 0x60]),
+    ("seagreen", [0x2e, 0x8b, 0x57]),
+    ("seashell", [0xff, 0xf5, 0xee]),
+    ("sienna", [0xa0, 0x52, 0x2d]),
+    ("silver", [0xc0, 0xc0, 0xc0]),
+    ("skyblue", [0x87, 0xce, 0xeb]),
+    ("slateblue", [0x6a, 0x5a, 0xcd]),
+    ("slategray", [0x70, 0x80, 0x90]),
+    ("slategrey", [0x70, 0x80, 0x90]),
+    ("snow", [0xff, 0xfa, 0xfa]),
+    ("springgreen", [0x00, 0xff, 0x7f]),
+    ("steelblue", [0x46, 0x82, 0xb4]),
+    ("tan", [0xd2, 0xb4, 0x8c]),
+    ("teal", [0x00, 0x80, 0x80]),
+    ("thistle", [0xd8, 0xbf, 0xd8]),
+    ("tomato", [0xff, 0x63, 0x47]),
+    ("turquoise", [0x40, 0xe0, 0xd0]),
+    ("violet", [0xee, 0x82, 0xee]),
+    ("wheat", [0xf5, 0xde, 0xb3]),
+    ("white", [0xff, 0xff, 0xff]),
+    ("whitesmoke", [0xf5, 0xf5, 0xf5]),
+    ("yellow", [0xff, 0xff, 0x00]),
+    ("yellowgreen", [0x9a, 0xcd, 0x32]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_refs(name: &str) -> Result<Srgba, ColorEvalError> {
+        Err(ColorEvalError::Undefined(name.to_string()))
+    }
+
+    #[test]
+    fn parse_expressions() {
+        let expr = ColorExpr::parse("alpha(@window_fg_color, .8)").unwrap();
+        assert_eq!(
+            expr,
+            ColorExpr::Alpha(Box::new(ColorExpr::Reference("window_fg_color".into())), 0.8)
+        );
+        assert_eq!(expr.references(), vec!["window_fg_color"]);
+
+        let expr = ColorExpr::parse("mix(shade(#fff, 0.5), darker(@a), 50%)").unwrap();
+        assert_eq!(expr.references(), vec!["a"]);
+
+        for text in ["mix(#fff, #000)", "shade(#fff)", "foo(#fff)", "alpha(#fff, .5", "#fff #000"] {
+            assert!(ColorExpr::parse(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn evaluate_expressions() {
+        let c = ColorExpr::parse("alpha(black, .8)")
+            .unwrap()
+            .evaluate(&mut no_refs)
+            .unwrap();
+        assert_eq!(c, Srgba::new(0.0, 0.0, 0.0, 0.8));
+
+        let c = ColorExpr::parse("mix(#000, #fff, 0.5)")
+            .unwrap()
+            .evaluate(&mut no_refs)
+            .unwrap();
+        assert_eq!(c, Srgba::new(0.5, 0.5, 0.5, 1.0));
+
+        let c = ColorExpr::parse("shade(#808080, 2)")
+            .unwrap()
+            .evaluate(&mut no_refs)
+            .unwrap();
+        let c: Srgba<u8> = c.into_format();
+        assert_eq!((c.red, c.green, c.blue), (255, 255, 255));
+
+        let err = ColorExpr::parse("lighter(@missing)")
+            .unwrap()
+            .evaluate(&mut no_refs)
+            .unwrap_err();
+        assert_eq!(err, ColorEvalError::Undefined("missing".into()));
+    }
+}
@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{atomic, NAME};
+
+/// first line of the block of generated color definitions
+pub const BLOCK_BEGIN: &'static str =
+    "/* BEGIN adw-user-colors: generated by adw-user-colors, changes inside this block will be lost */";
+/// last line of the block of generated color definitions
+pub const BLOCK_END: &'static str = "/* END adw-user-colors */";
+
+/// first line of stylesheets written by older versions, which replaced the whole file
+pub const LEGACY_HEADER: &'static str =
+    "/* Generated by adw-user-colors. Run `adw-user-colors --stop` to restore the original file. */";
+
+pub const BACKUP_DIR: &'static str = "backup";
+
+/// render the managed block for the given color definitions
+pub fn block(css: &str) -> String {
+    let mut block = String::new();
+    block.push_str(BLOCK_BEGIN);
+    block.push('\n');
+    block.push_str(css);
+    if !css.is_empty() && !css.ends_with('\n') {
+        block.push('\n');
+    }
+    block.push_str(BLOCK_END);
+    block.push('\n');
+    block
+}
+
+/// byte range of the managed block in a stylesheet, including the newline after the end marker
+/// fails if the end marker is missing, eg. after a hand edit, as the end of the block is unknown
+pub fn find_block(contents: &str) -> anyhow::Result<Option<Range<usize>>> {
+    let start = match contents
+        .match_indices(BLOCK_BEGIN)
+        .map(|(i, _)| i)
+        .find(|i| *i == 0 || contents.as_bytes()[i - 1] == b'\n')
+    {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+    let end = match contents[start..].find(BLOCK_END) {
+        Some(i) => start + i + BLOCK_END.len(),
+        None => anyhow::bail!("the generated colors are missing the line `{BLOCK_END}`"),
+    };
+    let end = if contents[end..].starts_with("\r\n") {
+        end + 2
+    } else if contents[end..].starts_with('\n') {
+        end + 1
+    } else {
+        end
+    };
+    Ok(Some(start..end))
+}
+
+/// insert the managed block at the top of a stylesheet after its `@import` rules, or replace it
+/// if it is already there
+/// everything outside the block is left untouched
+pub fn insert_block(contents: &str, css: &str) -> anyhow::Result<String> {
+    let block = block(css);
+    let mut new = contents.to_string();
+    match find_block(contents)? {
+        Some(range) => new.replace_range(range, &block),
+        None => {
+            let end = imports_end(contents);
+            if end == 0 || contents[..end].ends_with('\n') {
+                new.insert_str(end, &block);
+            } else {
+                new.insert_str(end, &format!("\n{block}"));
+            }
+        }
+    }
+    Ok(new)
+}
+
+/// remove the managed block from a stylesheet, leaving everything else byte-for-byte intact
+pub fn strip_block(contents: &str) -> anyhow::Result<String> {
+    let mut new = contents.to_string();
+    if let Some(range) = find_block(contents)? {
+        new.replace_range(range, "");
+    }
+    Ok(new)
+}
+
+// byte offset after the `@import` rules at the top of a stylesheet, css ignores imports after
+// other rules
+// blank lines and comments on their own line may come between them
+fn imports_end(contents: &str) -> usize {
+    let mut end = 0;
+    let mut pos = 0;
+    let mut in_import = false;
+    for line in contents.split_inclusive('\n') {
+        pos += line.len();
+        let line = line.trim();
+        let is_comment = line.starts_with("/*") && line.ends_with("*/");
+        if in_import || line.starts_with("@import") || line.starts_with("@charset") {
+            // rules may span several lines
+            in_import = !line.ends_with(';');
+            end = pos;
+        } else if !line.is_empty() && !is_comment {
+            break;
+        }
+    }
+    end
+}
+
+/// check whether a stylesheet was entirely written by an older version of the service
+pub fn is_legacy(contents: &str) -> bool {
+    contents.starts_with(LEGACY_HEADER)
+}
+
+/// path of the gtk.css for the given gtk config prefix, eg. `gtk-4.0`
+pub fn css_path(gtk_prefix: &str) -> anyhow::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(gtk_prefix)?;
+    Ok(xdg_dirs.place_config_file(PathBuf::from("gtk.css"))?)
+}
+
+fn backup_path(gtk_prefix: &str) -> anyhow::Result<PathBuf> {
+    let backup_path: PathBuf = [NAME, BACKUP_DIR, gtk_prefix].iter().collect();
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(backup_path)?;
+    Ok(xdg_dirs.place_state_file(PathBuf::from("gtk.css"))?)
+}
+
+/// read the user's part of a stylesheet
+/// files written by older versions are replaced by the backup they made of the original
+fn read_user_css(gtk_prefix: &str, css_path: &Path) -> anyhow::Result<String> {
+    let contents = match fs::read_to_string(css_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e.into()),
+    };
+    if !is_legacy(&contents) {
+        return Ok(contents);
+    }
+    let backup_path = backup_path(gtk_prefix)?;
+    match fs::read_to_string(&backup_path) {
+        Ok(c) => Ok(c),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn remove_backup(gtk_prefix: &str) -> anyhow::Result<()> {
+    let backup_path = backup_path(gtk_prefix)?;
+    match fs::remove_file(&backup_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// write the color definitions into the managed block of a stylesheet
+pub fn write_block(gtk_prefix: &str, css_path: &dyn AsRef<Path>, css: &str) -> anyhow::Result<()> {
+    let css_path = css_path.as_ref();
+    let user_css = read_user_css(gtk_prefix, css_path)?;
+    let new = insert_block(&user_css, css)?;
+    if fs::read_to_string(css_path).ok().as_ref() != Some(&new) {
+        atomic::write(css_path, &new)?;
+    }
+    remove_backup(gtk_prefix)
+}
+
+/// remove the managed block from a stylesheet
+/// if nothing but the block was left, the file is removed
+pub fn remove_block(gtk_prefix: &str, css_path: &dyn AsRef<Path>) -> anyhow::Result<()> {
+    let css_path = css_path.as_ref();
+    if !css_path.exists() {
+        return remove_backup(gtk_prefix);
+    }
+    let user_css = strip_block(&read_user_css(gtk_prefix, css_path)?)?;
+    if user_css.is_empty() {
+        fs::remove_file(css_path)?;
+        log::info!("removed generated {}", css_path.display());
+    } else {
+        atomic::write(css_path, &user_css)?;
+        log::info!("removed generated colors from {}", css_path.display());
+    }
+    remove_backup(gtk_prefix)
+}
+
+/// write the color definitions into the managed block of a stylesheet outside the user's gtk
+/// config, eg. in the config directory of a flatpak application
+/// missing parent directories are created
+pub fn write_block_at(css_path: &Path, css: &str) -> anyhow::Result<()> {
+    let contents = match fs::read_to_string(css_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let new = insert_block(&contents, css)?;
+    if new == contents {
+        return Ok(());
+    }
+    if let Some(parent) = css_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    atomic::write(css_path, &new)
+}
+
+/// remove the managed block from a stylesheet written by [`write_block_at`]
+/// if nothing but the block was left, the file is removed
+pub fn remove_block_at(css_path: &Path) -> anyhow::Result<()> {
+    let contents = match fs::read_to_string(css_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if find_block(&contents)?.is_none() {
+        return Ok(());
+    }
+    let user_css = strip_block(&contents)?;
+    if user_css.is_empty() {
+        fs::remove_file(css_path)?;
+        log::info!("removed generated {}", css_path.display());
+    } else {
+        atomic::write(css_path, &user_css)?;
+        log::info!("removed generated colors from {}", css_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_CSS: &'static str = "window {\n  color: red;\n}\n";
+
+    #[test]
+    fn insert_and_strip() {
+        let css = insert_block(USER_CSS, "@define-color accent_color #1c71d8;\n").unwrap();
+        assert!(css.ends_with(USER_CSS));
+        assert!(find_block(&css).unwrap().is_some());
+        assert_eq!(strip_block(&css).unwrap(), USER_CSS);
+    }
+
+    #[test]
+    fn replace_keeps_surroundings() {
+        let css = format!(
+            "/* mine */\n{}{}",
+            block("@define-color accent_color #1c71d8;\n"),
+            USER_CSS
+        );
+        let replaced = insert_block(&css, "@define-color accent_color #e01b24;\n").unwrap();
+        assert!(replaced.starts_with("/* mine */\n"));
+        assert!(replaced.ends_with(USER_CSS));
+        assert!(replaced.contains("#e01b24"));
+        assert!(!replaced.contains("#1c71d8"));
+        assert_eq!(replaced.matches(BLOCK_BEGIN).count(), 1);
+    }
+
+    #[test]
+    fn strip_without_block() {
+        assert_eq!(strip_block(USER_CSS).unwrap(), USER_CSS);
+        assert_eq!(strip_block(&insert_block("", "").unwrap()).unwrap(), "");
+    }
+
+    #[test]
+    fn after_imports() {
+        let imports = "@import url(\"a.css\");\n\n/* theme */\n@import url(\"b.css\")\n  screen;\n";
+        let user_css = format!("{imports}{USER_CSS}");
+        let css = insert_block(&user_css, "@define-color accent_color #1c71d8;\n").unwrap();
+        assert!(css.starts_with(&format!("{imports}{BLOCK_BEGIN}")));
+        assert_eq!(strip_block(&css).unwrap(), user_css);
+
+        // the end of the block is unknown without its end marker
+        let broken = format!("{BLOCK_BEGIN}\n@define-color accent_color #1c71d8;\n{USER_CSS}");
+        assert!(find_block(&broken).is_err());
+        assert!(insert_block(&broken, "").is_err());
+    }
+}
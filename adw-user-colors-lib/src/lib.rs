@@ -1,117 +1,396 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{
+    cell::RefCell, collections::BTreeMap, path::PathBuf, rc::Rc, sync::Mutex, thread,
+    time::Duration,
+};
 
 use adw::{
-    glib::{MainContext, MainLoop, Priority},
-    StyleManager,
+    glib::{self, MainContext, MainLoop, Priority},
+    prelude::Continue,
 };
-use colors::ColorOverrides;
+use color_scheme::ColorScheme;
+use colors::{ColorOverrides, CssMode};
 use config::Config;
+use library::ThemeLibrary;
+use output::Output;
 
+pub mod apps;
+pub mod atomic;
+pub mod color_scheme;
 pub mod colors;
 pub mod config;
+pub mod dbus;
+pub mod expr;
+pub mod gtk_css;
+pub mod library;
+pub mod migration;
+pub mod output;
+pub mod schedule;
+pub mod systemd;
+pub mod watch;
+pub mod widgets;
 
 pub const NAME: &'static str = "adwaita-user-colors";
 pub const THEME_DIR: &'static str = "color-overrides";
-use futures::{channel::mpsc::channel, SinkExt, StreamExt};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+pub const PID_FILE: &'static str = "service.pid";
+pub const GTK4_PREFIX: &'static str = "gtk-4.0";
+pub const GTK3_PREFIX: &'static str = "gtk-3.0";
+use futures::{channel::mpsc::unbounded, StreamExt};
+use notify::event::Flag;
+use systemd::NotifyState;
 
 enum Event {
     UpdateColors,
+    /// re-render even if nothing changed
+    Reload,
 }
 
 // load selected cosmic-theme
 pub fn load() -> anyhow::Result<()> {
-    adw::gtk::init()?;
-    adw::init();
     let config_dir_path = Config::init()?;
     let color_dir_path = ColorOverrides::init()?;
 
     let theme = config::Config::load()?;
-    let active = theme.active_name();
-    if active.is_none() {
-        anyhow::bail!("no configured theme");
+    let source = color_scheme::source(theme.color_scheme_backend)?;
+
+    if let Err(e) = write_pid_file() {
+        log::warn!("failed to write pid file: {e}");
     }
-    let active = active.unwrap();
 
-    let css_path: PathBuf = [NAME, THEME_DIR].iter().collect();
-    let css_dirs = xdg::BaseDirectories::with_prefix(css_path)?;
+    // FIXME
+    let main_context = MainContext::default();
+    let (tx, rx) = MainContext::channel(Priority::default());
 
-    if let Some(active_theme_path) = css_dirs.find_data_file(format!("{active}.ron")) {
-        let active_theme_file = File::open(active_theme_path)?;
-        let reader = BufReader::new(active_theme_file);
-        let overrides: colors::ColorOverrides = ron::de::from_reader(reader)?;
+    let tx_clone = Mutex::new(tx.clone());
+    let control = match gio::bus_get_sync(gio::BusType::Session, None::<&gio::Cancellable>)
+        .map_err(anyhow::Error::from)
+        .and_then(|connection| {
+            dbus::ControlInterface::export(connection, ThemeLibrary::new()?, move |request| {
+                let event = match request {
+                    dbus::Request::UpdateColors => Event::UpdateColors,
+                    dbus::Request::Reload => Event::Reload,
+                };
+                let _ = tx_clone.lock().unwrap().send(event);
+            })
+        }) {
+        Ok(control) => Some(control),
+        Err(e) => {
+            log::warn!("failed to export the D-Bus interface: {e}");
+            None
+        }
+    };
 
-        let mut user_color_css = String::new();
-        user_color_css.push_str(&overrides.as_css());
-        user_color_css.push_str(&format!("\n@import url(\"custom.css\");\n"));
+    let tx_clone = tx.clone();
+    source.connect_changed(Box::new(move |_| {
+        let _ = tx_clone.send(Event::UpdateColors);
+    }));
 
-        let xdg_dirs = xdg::BaseDirectories::with_prefix("gtk-4.0")?;
-        let path = xdg_dirs.place_config_file(PathBuf::from("gtk.css"))?;
+    // the schedule takes precedence over the system color scheme
+    let active_color_scheme = move |config: &Config| match &config.schedule {
+        Some(schedule) => schedule.color_scheme(),
+        None => source.color_scheme(),
+    };
 
-        std::fs::write(&path, &user_color_css)?;
-    }
+    // outputs of the last config that loaded, cleaned up on exit if the config is broken by then
+    let last_outputs = Rc::new(RefCell::new(theme.outputs.clone()));
 
-    // FIXME
-    let main_context = MainContext::default();
-    let (tx, rx) = MainContext::channel(Priority::default());
+    let mut rendered = None;
+    render_active(
+        &theme,
+        active_color_scheme(&theme),
+        control.as_ref(),
+        &mut rendered,
+    );
+
+    // the schedule is checked every minute, which also notices clock changes and resuming
     let tx_clone = tx.clone();
-    main_context.spawn_local(async move {
-        let style_manager = StyleManager::default();
-        style_manager.connect_color_scheme_notify(move |_| {
+    let mut scheduled = None;
+    let mut check_schedule = move || {
+        let schedule = match Config::load() {
+            Ok(config) => config.schedule,
+            Err(_) => return,
+        };
+        let current = schedule
+            .as_ref()
+            .map(|s| (s.color_scheme(), s.set_color_scheme));
+        if current != scheduled {
+            scheduled = current;
+            if let Some((color_scheme, true)) = current {
+                if let Err(e) = color_scheme::set_system_color_scheme(color_scheme) {
+                    log::warn!("failed to set the system color scheme: {e}");
+                }
+            }
             let _ = tx_clone.send(Event::UpdateColors);
-        });
+        }
+    };
+    check_schedule();
+    glib::timeout_add_seconds_local(60, move || {
+        check_schedule();
+        Continue(true)
     });
 
     let tx_clone = tx.clone();
     main_context.spawn_local(async move {
-        let (mut tx, mut rx) = channel(1);
-
-        // Automatically select the best implementation for your platform.
-        // You can also access each implementation directly e.g. INotifyWatcher.
-        let mut watcher = RecommendedWatcher::new(move |res| {
-            futures::executor::block_on(async {
-                tx.send(res).await.unwrap();
-            })
-        })
-        .unwrap();
-        let _ = watcher
-            .watch(&config_dir_path, RecursiveMode::Recursive)
-            .unwrap();
-        let _ = watcher.watch(&color_dir_path.as_ref(), RecursiveMode::Recursive);
+        let (tx, mut rx) = unbounded();
+        let dirs = vec![config_dir_path, color_dir_path];
+        let mut watcher = watch::DirWatcher::new_retrying(dirs, tx).await;
+        watcher.arm().await;
 
+        // editors often write a file several times when saving
+        let debouncer = watch::Debouncer::new(watch::DEBOUNCE, move || {
+            let _ = tx_clone.send(Event::UpdateColors);
+        });
         while let Some(res) = rx.next().await {
             match res {
-                Ok(e) => match e.kind {
-                    // TODO only notify for changed data file if it is the active file
-                    notify::EventKind::Create(_) | notify::EventKind::Modify(_) => {
-                        let _ = tx_clone.send(Event::UpdateColors);
+                Ok(e) if watcher.is_dir_lost(&e) => {
+                    log::warn!("watched directory was removed or replaced, watching it again");
+                    watcher.arm().await;
+                    debouncer.call();
+                }
+                // events were dropped
+                Ok(e) if e.flag() == Some(Flag::Rescan) => debouncer.call(),
+                // the active themes may have changed since the last event
+                Ok(e) => match watch::WatchFilter::load() {
+                    Ok(filter) if !filter.matches(&e) => {}
+                    Ok(_) => debouncer.call(),
+                    Err(err) => {
+                        log::warn!("failed to load the active themes: {err}");
+                        debouncer.call();
                     }
-                    _ => {}
                 },
-                Err(e) => eprintln!("watch error: {:?}", e),
+                Err(e) => {
+                    log::warn!("watch error, watching the directories again: {e}");
+                    watcher.arm().await;
+                    debouncer.call();
+                }
             }
         }
     });
 
-    rx.attach(Some(&main_context), move |_| {
-        if let Ok(overrides) = ColorOverrides::load_active() {
-            let user_color_css = &mut overrides.as_css().to_string();
-            user_color_css.push_str(&format!("\n@import url(\"custom.css\");\n"));
-            if let Ok(xdg_dirs) = xdg::BaseDirectories::with_prefix("gtk-4.0") {
-                if let Ok(path) = xdg_dirs.place_config_file(PathBuf::from("gtk.css")) {
-                    let _ = std::fs::write(&path, &user_color_css);
-                }
+    let last_outputs_clone = last_outputs.clone();
+    rx.attach(Some(&main_context), move |event| {
+        let reload = matches!(event, Event::Reload);
+        if reload {
+            log::info!("reloading");
+            systemd::notify(&[NotifyState::Reloading]);
+            rendered = None;
+        }
+        match Config::load() {
+            Ok(config) => {
+                render_active(
+                    &config,
+                    active_color_scheme(&config),
+                    control.as_ref(),
+                    &mut rendered,
+                );
+                last_outputs_clone.replace(config.outputs);
             }
+            Err(e) => log::error!("failed to load config: {e}"),
         }
-        adw::prelude::Continue(true)
+        if reload {
+            systemd::notify(&[NotifyState::Ready]);
+        }
+        Continue(true)
+    });
+
+    let main_loop = MainLoop::new(Some(&main_context), false);
+    glib::unix_signal_add_local(libc::SIGHUP, move || {
+        let _ = tx.send(Event::Reload);
+        Continue(true)
+    });
+    let main_loop_clone = main_loop.clone();
+    glib::unix_signal_add_local(libc::SIGTERM, move || {
+        log::info!("stopping");
+        systemd::notify(&[NotifyState::Stopping]);
+        main_loop_clone.quit();
+        Continue(false)
     });
-    let main_loop = MainLoop::new(Some(&main_context), true);
+
+    systemd::notify(&[NotifyState::Ready]);
     main_loop.run();
-    Ok(())
+
+    remove_pid_file();
+    let res = apps::remove_all();
+    let outputs = match Config::load() {
+        Ok(config) => config.outputs,
+        Err(e) => {
+            log::warn!("failed to load config, removing the outputs it had last: {e}");
+            last_outputs.take()
+        }
+    };
+    output::remove_all(&outputs).and(res)
 }
 
+// apply the active theme, and report the result to the service manager
+fn render_active(
+    config: &Config,
+    color_scheme: ColorScheme,
+    control: Option<&dbus::ControlInterface>,
+    rendered: &mut Option<Rendered>,
+) {
+    let active = config.theme_name(color_scheme);
+    let res = apply_theme(config, color_scheme, control, rendered);
+    if let Err(e) = &res {
+        log::error!("failed to apply theme: {e}");
+    }
+    let status = systemd::status(config, active, &res);
+    systemd::notify(&[NotifyState::Status(&status)]);
+}
+
+/// render the active theme to all enabled outputs once, without starting the service
+/// the color scheme is taken from the schedule or detected with the configured backend unless
+/// it is given, and light is used if it can't be detected, eg. in build environments without a session
+/// returns the name of the applied theme
+pub fn apply(color_scheme: Option<ColorScheme>) -> anyhow::Result<String> {
+    Config::init()?;
+    let config = Config::load()?;
+    let color_scheme = color_scheme.unwrap_or_else(|| match config.color_scheme() {
+        Ok(color_scheme) => color_scheme,
+        Err(e) => {
+            log::warn!("failed to read the color scheme, using light: {e}");
+            ColorScheme::Light
+        }
+    });
+    let name = config.theme_name(color_scheme);
+    if name.is_empty() {
+        anyhow::bail!("no theme is selected for the {color_scheme:?} color scheme");
+    }
+    apply_theme(&config, color_scheme, None, &mut None)?;
+    Ok(name.to_string())
+}
+
+// what was last written to the outputs
+#[derive(PartialEq)]
+struct Rendered {
+    name: String,
+    css: String,
+    outputs: Vec<Output>,
+    /// css of the application stylesheets by path
+    apps: BTreeMap<PathBuf, String>,
+}
+
+// render the theme of the color scheme to all outputs and application stylesheets, and announce
+// it on the control interface
+// nothing is written if the themes and outputs are unchanged since the last time
+fn apply_theme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    control: Option<&dbus::ControlInterface>,
+    last: &mut Option<Rendered>,
+) -> anyhow::Result<()> {
+    // applications with their own theme are themed even if the desktop theme fails to load
+    let apps = apps::render_all(config, color_scheme);
+    let apps_res = match last {
+        Some(last) if last.apps == apps => Ok(()),
+        _ => apps::write_rendered(&apps),
+    };
+
+    let name = config.theme_name(color_scheme);
+    let overrides = ColorOverrides::load_from_name(name)?.resolve()?;
+    let rendered = Rendered {
+        name: name.to_string(),
+        css: overrides.as_css(CssMode::Symbolic),
+        outputs: config.outputs.clone(),
+        apps,
+    };
+    if last.as_ref() == Some(&rendered) {
+        log::debug!("{name} is unchanged, skipping");
+        return apps_res;
+    }
+    output::write_all(&config.outputs, &overrides)?;
+    if let Some(control) = control {
+        control.set_applied(name, rendered.css.clone());
+    }
+    *last = Some(rendered);
+    apps_res
+}
+
+/// stop a running instance and remove the generated colors from all outputs
 pub fn unload() -> anyhow::Result<()> {
-    todo!();
+    if let Err(e) = stop_running_instance() {
+        log::warn!("failed to stop running instance: {e}");
+    }
+    // the written outputs are recorded, so a broken config doesn't keep the colors applied
+    let outputs = match Config::load() {
+        Ok(config) => config.outputs,
+        Err(e) => {
+            log::warn!("failed to load config, removing the default outputs: {e}");
+            Output::defaults()
+        }
+    };
+    let res = apps::remove_all();
+    output::remove_all(&outputs).and(res)
+}
+
+fn write_pid_file() -> anyhow::Result<()> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+    let path = xdg_dirs.place_runtime_file(PID_FILE)?;
+    std::fs::write(path, std::process::id().to_string())?;
+    Ok(())
+}
+
+// only the pid file of this process is removed, another instance may have replaced it
+fn remove_pid_file() {
+    let xdg_dirs = match xdg::BaseDirectories::with_prefix(NAME) {
+        Ok(d) => d,
+        Err(_) => return,
+    };
+    if let Some(path) = xdg_dirs.find_runtime_file(PID_FILE) {
+        let pid = std::fs::read_to_string(&path).unwrap_or_default();
+        if pid.trim() == std::process::id().to_string() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// pid of the running service, if there is one
+pub fn running_pid() -> Option<libc::pid_t> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME).ok()?;
+    let path = xdg_dirs.find_runtime_file(PID_FILE)?;
+    let pid: libc::pid_t = std::fs::read_to_string(&path).ok()?.trim().parse().ok()?;
+    // SAFETY: kill has no memory safety requirements
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    (alive && is_instance(pid) && pid != std::process::id() as libc::pid_t).then_some(pid)
+}
+
+fn stop_running_instance() -> anyhow::Result<()> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+    let path = match xdg_dirs.find_runtime_file(PID_FILE) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let pid = running_pid();
+    std::fs::remove_file(&path)?;
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return Ok(()),
+    };
+
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        // the instance is already gone
+        return Ok(());
+    }
+    // wait for the instance to exit so it can't rewrite gtk.css after it is restored
+    for _ in 0..50 {
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    anyhow::bail!("instance with pid {pid} did not exit")
+}
+
+// guard against signalling an unrelated process that reused a stale pid
+fn is_instance(pid: libc::pid_t) -> bool {
+    std::fs::read(format!("/proc/{pid}/cmdline"))
+        .map(|cmdline| {
+            cmdline
+                .split(|b| *b == 0)
+                .next()
+                .map(|exe| exe.ends_with(b"adw-user-colors"))
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
 }
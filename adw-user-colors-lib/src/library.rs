@@ -0,0 +1,375 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    atomic,
+    colors::{ColorOverrides, DARK_DEFAULT, LIGHT_DEFAULT},
+    config::Config,
+    NAME, THEME_DIR,
+};
+
+/// where a theme is installed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeSource {
+    /// the user's theme directory, the theme can be changed
+    User,
+    /// a theme directory in `XDG_DATA_DIRS`, the theme is read-only
+    System,
+    /// shipped with the library
+    Builtin,
+}
+
+/// a theme found in the library
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThemeEntry {
+    pub name: String,
+    pub source: ThemeSource,
+    /// path of the theme file, built-in themes have none
+    pub path: Option<PathBuf>,
+    /// the theme pack of a system theme, the subdirectory of the system theme directory it is in
+    pub pack: Option<String>,
+}
+
+impl ThemeEntry {
+    /// only the user's themes can be changed, others have to be copied to the user's themes first
+    pub fn is_read_only(&self) -> bool {
+        self.source != ThemeSource::User
+    }
+
+    /// load the theme from its file, or the built-in theme
+    pub fn load(&self) -> anyhow::Result<ColorOverrides> {
+        match &self.path {
+            Some(path) => ColorOverrides::load(path),
+            None => ColorOverrides::builtin(&self.name)
+                .ok_or_else(|| anyhow::anyhow!("Could not find theme {}", self.name)),
+        }
+    }
+}
+
+/// the themes in the user's and the system theme directories
+/// themes are looked up in the user's directory first, then in the system directories in the
+/// order of `XDG_DATA_DIRS`, then in the built-in themes, and the first theme with a name hides
+/// the others
+/// distributions ship themes in `<data dir>/adwaita-user-colors/color-overrides`, and curated
+/// theme packs in its subdirectories, eg. `/usr/share/adwaita-user-colors/color-overrides/gnome/`
+/// themes directly in a system directory come before those in packs, and packs are searched in
+/// alphabetical order
+#[derive(Debug, Clone)]
+pub struct ThemeLibrary {
+    /// the user's theme directory, where themes are written
+    pub user_dir: PathBuf,
+    /// the system theme directories, in order of precedence
+    pub system_dirs: Vec<PathBuf>,
+}
+
+impl ThemeLibrary {
+    /// library of the theme directories in the XDG base directories
+    pub fn new() -> anyhow::Result<Self> {
+        let theme_dir: PathBuf = [NAME, THEME_DIR].iter().collect();
+        let base_dirs = xdg::BaseDirectories::new()?;
+        Ok(Self {
+            user_dir: base_dirs.get_data_home().join(&theme_dir),
+            system_dirs: base_dirs
+                .get_data_dirs()
+                .into_iter()
+                .map(|d| d.join(&theme_dir))
+                .collect(),
+        })
+    }
+
+    /// all themes by name, each name is listed once with the theme that is used for it
+    pub fn list(&self) -> anyhow::Result<Vec<ThemeEntry>> {
+        let mut entries: Vec<ThemeEntry> = Vec::new();
+        let user = theme_files(&self.user_dir)?
+            .into_iter()
+            .map(|p| (ThemeSource::User, None, p));
+        let mut system = Vec::new();
+        for dir in &self.system_dirs {
+            system.extend(
+                system_theme_files(dir)?
+                    .into_iter()
+                    .map(|(pack, p)| (ThemeSource::System, pack, p)),
+            );
+        }
+        for (source, pack, path) in user.chain(system) {
+            let name = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if !entries.iter().any(|e| e.name == name) {
+                entries.push(ThemeEntry {
+                    name,
+                    source,
+                    path: Some(path),
+                    pack,
+                });
+            }
+        }
+        for name in [LIGHT_DEFAULT, DARK_DEFAULT] {
+            if !entries.iter().any(|e| e.name == name) {
+                entries.push(ThemeEntry {
+                    name: name.to_string(),
+                    source: ThemeSource::Builtin,
+                    path: None,
+                    pack: None,
+                });
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    /// the theme used for the name
+    pub fn find(&self, name: &str) -> anyhow::Result<Option<ThemeEntry>> {
+        Ok(self.list()?.into_iter().find(|e| e.name == name))
+    }
+
+    /// load the theme used for the name
+    pub fn load(&self, name: &str) -> anyhow::Result<ColorOverrides> {
+        match self.find(name)? {
+            Some(entry) => entry.load(),
+            None => anyhow::bail!("Could not find theme {name}"),
+        }
+    }
+
+    /// copy a theme to the user's theme directory under a new name
+    /// system and built-in themes can be duplicated to change them
+    pub fn duplicate(&self, name: &str, new_name: &str) -> anyhow::Result<ColorOverrides> {
+        self.check_new_name(new_name)?;
+        let mut theme = self.load(name)?;
+        theme.name = new_name.to_string();
+        self.write(&theme)?;
+        Ok(theme)
+    }
+
+    /// copy a system or built-in theme to the user's theme directory under the same name, the copy
+    /// is used instead of it and can be changed
+    pub fn copy_to_user(&self, name: &str) -> anyhow::Result<ColorOverrides> {
+        match self.find(name)? {
+            Some(entry) if entry.is_read_only() => {}
+            Some(_) => anyhow::bail!("theme {name} is already one of the user's themes"),
+            None => anyhow::bail!("Could not find theme {name}"),
+        }
+        let mut theme = self.load(name)?;
+        theme.name = name.to_string();
+        self.write(&theme)?;
+        Ok(theme)
+    }
+
+    /// rename a theme in the user's theme directory
+    /// themes inheriting from it and the config are changed to the new name
+    pub fn rename(&self, name: &str, new_name: &str) -> anyhow::Result<()> {
+        let path = self.user_path_of(name)?;
+        self.check_new_name(new_name)?;
+        let mut theme = ColorOverrides::load(&path)?;
+        theme.name = new_name.to_string();
+        self.write(&theme)?;
+        fs::remove_file(&path)?;
+
+        for (path, mut child) in self.children(name)? {
+            child.base = Some(new_name.to_string());
+            atomic::write(&path, child.to_ron()?)?;
+        }
+        // a system theme with the old name may be uncovered, but the user meant the renamed one
+        update_config(name, Some(new_name))
+    }
+
+    /// delete a theme from the user's theme directory
+    /// if no system or built-in theme of the same name is left, references to it are removed
+    /// from the config, and themes inheriting from it prevent deleting it
+    pub fn delete(&self, name: &str) -> anyhow::Result<()> {
+        let path = self.user_path_of(name)?;
+        let replaced = self.is_shadowing(name)?;
+        if !replaced {
+            let children: Vec<_> = self
+                .children(name)?
+                .into_iter()
+                .map(|(_, t)| t.name)
+                .collect();
+            if !children.is_empty() {
+                anyhow::bail!("theme {name} is the base of {}", children.join(", "));
+            }
+        }
+        fs::remove_file(&path)?;
+        if replaced {
+            Ok(())
+        } else {
+            update_config(name, None)
+        }
+    }
+
+    // path of a theme in the user's directory, other themes are read-only
+    fn user_path_of(&self, name: &str) -> anyhow::Result<PathBuf> {
+        match self.find(name)? {
+            Some(ThemeEntry {
+                source: ThemeSource::User,
+                path: Some(path),
+                ..
+            }) => Ok(path),
+            Some(_) => anyhow::bail!("theme {name} is read-only, duplicate it to change it"),
+            None => anyhow::bail!("Could not find theme {name}"),
+        }
+    }
+
+    // whether a system or built-in theme of the same name is hidden by the user's theme
+    fn is_shadowing(&self, name: &str) -> anyhow::Result<bool> {
+        if ColorOverrides::builtin(name).is_some() {
+            return Ok(true);
+        }
+        for dir in &self.system_dirs {
+            if system_theme_files(dir)?
+                .iter()
+                .any(|(_, p)| p.file_stem() == Some(name.as_ref()))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn check_new_name(&self, name: &str) -> anyhow::Result<()> {
+        if !is_valid_name(name) {
+            anyhow::bail!("invalid theme name {name:?}");
+        }
+        if self.find(name)?.is_some() {
+            anyhow::bail!("theme {name} already exists");
+        }
+        Ok(())
+    }
+
+    // the user's themes inheriting from a theme, and their paths
+    fn children(&self, name: &str) -> anyhow::Result<Vec<(PathBuf, ColorOverrides)>> {
+        Ok(theme_files(&self.user_dir)?
+            .into_iter()
+            .filter_map(|p| Some((p.clone(), ColorOverrides::load(&p).ok()?)))
+            .filter(|(_, t)| t.base.as_deref() == Some(name))
+            .collect())
+    }
+
+    fn write(&self, theme: &ColorOverrides) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.user_dir)?;
+        let path = self.user_dir.join(format!("{}.ron", theme.name));
+        atomic::write(&path, theme.to_ron()?)
+    }
+}
+
+/// check whether the string can be used as the name of a theme file
+pub fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && !name.starts_with('.') && !name.contains(['/', '\0'])
+}
+
+// the theme files in a directory, sorted
+fn theme_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<_> = read_dir(dir)?
+        .into_iter()
+        .filter(|p| p.extension() == Some("ron".as_ref()) && !atomic::is_temp_file(p))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// the theme files in a system directory and in its theme packs, with the name of their pack
+fn system_theme_files(dir: &Path) -> anyhow::Result<Vec<(Option<String>, PathBuf)>> {
+    let mut files: Vec<_> = theme_files(dir)?.into_iter().map(|p| (None, p)).collect();
+    let mut packs: Vec<_> = read_dir(dir)?.into_iter().filter(|p| p.is_dir()).collect();
+    packs.sort();
+    for pack in packs {
+        let name = pack.file_name().map(|n| n.to_string_lossy().into_owned());
+        files.extend(theme_files(&pack)?.into_iter().map(|p| (name.clone(), p)));
+    }
+    Ok(files)
+}
+
+// the paths in a directory, a missing directory is empty
+fn read_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.flatten().map(|e| e.path()).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn update_config(name: &str, new_name: Option<&str>) -> anyhow::Result<()> {
+    let mut config = Config::load()?;
+    if config.replace_theme(name, new_name) {
+        config.save()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn list_and_duplicate() {
+        let dir =
+            std::env::temp_dir().join(format!("adw-user-colors-library-{}", std::process::id()));
+        let library = ThemeLibrary {
+            user_dir: dir.join("user"),
+            system_dirs: vec![dir.join("system")],
+        };
+        let themes = [
+            ("red", "system"),
+            ("blue", "system"),
+            ("blue", "user"),
+            // packs come after the themes directly in a system directory
+            ("red", "system/pack"),
+            ("purple", "system/pack"),
+        ];
+        for (name, theme_dir) in themes {
+            let mut theme = ColorOverrides::light_default();
+            theme.name = name.to_string();
+            let theme_dir = dir.join(theme_dir);
+            fs::create_dir_all(&theme_dir).unwrap();
+            let path = theme_dir.join(format!("{name}.ron"));
+            fs::write(path, theme.to_ron().unwrap()).unwrap();
+        }
+
+        let entries = library.list().unwrap();
+        let sources: Vec<_> = entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.source, e.pack.as_deref()))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("blue", ThemeSource::User, None),
+                (DARK_DEFAULT, ThemeSource::Builtin, None),
+                (LIGHT_DEFAULT, ThemeSource::Builtin, None),
+                ("purple", ThemeSource::System, Some("pack")),
+                ("red", ThemeSource::System, None),
+            ]
+        );
+
+        // read-only themes are copied to the user's directory
+        assert!(library.duplicate("red", "blue").is_err());
+        assert!(library.duplicate("red", "../red").is_err());
+        assert_eq!(library.duplicate("red", "green").unwrap().name, "green");
+        assert_eq!(
+            library.find("green").unwrap().unwrap().source,
+            ThemeSource::User
+        );
+        assert!(library.is_shadowing("blue").unwrap());
+        assert!(!library.is_shadowing("green").unwrap());
+        assert!(library.user_path_of("red").is_err());
+
+        // copies hide the read-only themes
+        assert!(library.copy_to_user("blue").is_err());
+        library.copy_to_user(DARK_DEFAULT).unwrap();
+        let copy = library.find(DARK_DEFAULT).unwrap().unwrap();
+        assert!(!copy.is_read_only());
+        assert_eq!(copy.path, Some(library.user_dir.join("dark_default.ron")));
+
+        let mut config = Config::new("red".into(), "green".into());
+        assert!(config.replace_theme("green", Some("teal")));
+        assert!(config.replace_theme("red", None));
+        assert_eq!((config.light.as_str(), config.dark.as_str()), ("", "teal"));
+        assert!(!config.replace_theme("red", None));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use ron::{Map, Value};
+
+use crate::colors::color_key;
+
+/// version of the theme file format written by this version of the library
+pub const THEME_VERSION: u32 = 1;
+
+/// keys of a theme file which are not colors
+pub const THEME_FIELDS: &[&str] = &["version", "name", "base"];
+
+/// upgrades a theme from the version at its index to the next version
+type Migration = fn(&mut Map, &mut Vec<String>);
+
+const MIGRATIONS: &[Migration] = &[v0_to_v1];
+
+/// a theme file upgraded to the current version
+#[derive(Debug, Clone)]
+pub struct Migrated {
+    pub value: Value,
+    /// version of the file before it was upgraded
+    pub from_version: u32,
+    /// problems found in the file, which were fixed or ignored
+    pub warnings: Vec<String>,
+}
+
+impl Migrated {
+    /// check whether the file on disk should be replaced by the upgraded theme
+    /// files from newer versions are never replaced
+    pub fn is_outdated(&self) -> bool {
+        self.from_version < THEME_VERSION
+            || (self.from_version == THEME_VERSION && !self.warnings.is_empty())
+    }
+}
+
+/// upgrade a parsed theme file to the current version
+/// unknown keys are dropped, and files from newer versions are read as well as possible
+pub fn migrate(value: Value) -> anyhow::Result<Migrated> {
+    let mut map = match value {
+        Value::Map(map) => map,
+        _ => anyhow::bail!("expected a theme struct"),
+    };
+    let mut warnings = Vec::new();
+
+    let from_version = match map.remove(&key("version")) {
+        None => 0,
+        Some(Value::Number(n)) => match n.as_i64().map(u32::try_from) {
+            Some(Ok(v)) => v,
+            _ => anyhow::bail!("invalid theme version {}", n.into_f64()),
+        },
+        Some(v) => anyhow::bail!("invalid theme version {v:?}"),
+    };
+    if from_version > THEME_VERSION {
+        warnings.push(format!(
+            "theme version {from_version} is newer than the supported version {THEME_VERSION}"
+        ));
+    }
+    for migration in MIGRATIONS.iter().skip(from_version as usize) {
+        migration(&mut map, &mut warnings);
+    }
+
+    let unknown: Vec<_> = map
+        .keys()
+        .filter(|k| match k {
+            Value::String(k) => !THEME_FIELDS.contains(&k.as_str()) && color_key(k).is_none(),
+            _ => true,
+        })
+        .cloned()
+        .collect();
+    for k in unknown {
+        warnings.push(format!("ignoring unknown key {}", display_key(&k)));
+        map.remove(&k);
+    }
+
+    // colors written without `Some(..)`
+    for (k, v) in map.iter_mut() {
+        if matches!(k, Value::String(k) if k != "name") && matches!(v, Value::String(_)) {
+            *v = Value::Option(Some(Box::new(v.clone())));
+        }
+    }
+
+    let version = from_version.max(THEME_VERSION);
+    map.insert(key("version"), Value::Number(i64::from(version).into()));
+    Ok(Migrated {
+        value: Value::Map(map),
+        from_version,
+        warnings,
+    })
+}
+
+// themes without a version predate the format being versioned, and only gain the version field
+fn v0_to_v1(_map: &mut Map, _warnings: &mut Vec<String>) {}
+
+fn key(k: &str) -> Value {
+    Value::String(k.to_string())
+}
+
+fn display_key(k: &Value) -> String {
+    match k {
+        Value::String(k) => format!("`{k}`"),
+        k => format!("{k:?}"),
+    }
+}
@@ -0,0 +1,436 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atomic,
+    colors::{Color, ColorOverrides, CssMode},
+    expr::ColorEvalError,
+    gtk_css, GTK3_PREFIX, GTK4_PREFIX, NAME,
+};
+
+/// state file listing the outputs written by the service
+pub const WRITTEN_FILE: &'static str = "outputs.ron";
+/// first line of css files which are entirely generated by the service
+pub const CSS_HEADER: &'static str = "/* Generated by adw-user-colors */";
+/// first line of terminal theme files which are entirely generated by the service
+pub const TERMINAL_HEADER: &'static str = "# Generated by adw-user-colors";
+
+/// terminal palette entries, and the colors they are rendered from
+pub const TERMINAL_COLORS: &[(&str, &str)] = &[
+    ("background", "view_bg_color"),
+    ("foreground", "view_fg_color"),
+    ("cursor", "accent_color"),
+    ("selection_background", "accent_bg_color"),
+    ("selection_foreground", "accent_fg_color"),
+    ("color1", "error_color"),
+    ("color2", "success_color"),
+    ("color3", "warning_color"),
+    ("color4", "accent_color"),
+];
+
+/// a destination the active color overrides are rendered to
+pub trait OutputTarget {
+    /// path of the rendered file
+    fn path(&self) -> &Path;
+
+    /// render the overrides in the format of the target
+    fn render(&self, overrides: &ColorOverrides) -> String;
+
+    /// write the rendered overrides to the target
+    fn write(&self, overrides: &ColorOverrides) -> anyhow::Result<()>;
+
+    /// remove everything the service wrote to the target
+    fn remove(&self) -> anyhow::Result<()>;
+}
+
+/// format of an output target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum OutputFormat {
+    /// managed block in a GTK 4 stylesheet
+    Gtk4,
+    /// managed block in a GTK 3 stylesheet
+    Gtk3,
+    /// css file containing only the color definitions
+    Css,
+    /// terminal theme file in `key #rrggbb` format
+    Terminal,
+}
+
+/// an output target registered in the config
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Output {
+    pub format: OutputFormat,
+    /// path of the rendered file, relative paths are resolved against the XDG config home
+    /// the default location for the format is used if unset
+    pub path: Option<PathBuf>,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, path: Option<PathBuf>) -> Self {
+        Self {
+            format,
+            path,
+            enabled: true,
+        }
+    }
+
+    /// the outputs used when none are configured
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new(OutputFormat::Gtk4, None),
+            Self::new(OutputFormat::Gtk3, None),
+        ]
+    }
+
+    /// resolve the path of the output
+    pub fn path(&self) -> anyhow::Result<PathBuf> {
+        let base_dirs = xdg::BaseDirectories::new()?;
+        match &self.path {
+            Some(p) if p.is_absolute() => Ok(p.clone()),
+            Some(p) => Ok(base_dirs.get_config_home().join(p)),
+            None => match self.format {
+                OutputFormat::Gtk4 => gtk_css::css_path(GTK4_PREFIX),
+                OutputFormat::Gtk3 => gtk_css::css_path(GTK3_PREFIX),
+                OutputFormat::Css => {
+                    let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+                    Ok(xdg_dirs.place_config_file("colors.css")?)
+                }
+                OutputFormat::Terminal => {
+                    let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+                    Ok(xdg_dirs.place_config_file("terminal.conf")?)
+                }
+            },
+        }
+    }
+
+    /// the output with its path resolved, as recorded in the written outputs
+    pub fn resolved(&self) -> anyhow::Result<Self> {
+        Ok(Self::new(self.format, Some(self.path()?)))
+    }
+
+    /// create the implementation of the output target
+    pub fn target(&self) -> anyhow::Result<Box<dyn OutputTarget>> {
+        let path = self.path()?;
+        Ok(match self.format {
+            OutputFormat::Gtk4 => Box::new(Gtk4Target { path }),
+            OutputFormat::Gtk3 => Box::new(Gtk3Target { path }),
+            OutputFormat::Css => Box::new(CssTarget { path }),
+            OutputFormat::Terminal => Box::new(TerminalTarget { path }),
+        })
+    }
+}
+
+/// outputs the service wrote to, so they can be cleaned up even if the config changed since or
+/// fails to load
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Written {
+    /// the written outputs, with their resolved paths
+    pub outputs: Vec<Output>,
+}
+
+impl Written {
+    fn path() -> anyhow::Result<PathBuf> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix(NAME)?;
+        Ok(xdg_dirs.place_state_file(WRITTEN_FILE)?)
+    }
+
+    /// load the written outputs, nothing was written if the state file is missing
+    pub fn load() -> anyhow::Result<Self> {
+        match fs::read_to_string(Self::path()?) {
+            Ok(s) => Ok(ron::from_str(&s)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let ron = ron::ser::to_string_pretty(self, Default::default())?;
+        atomic::write(&Self::path()?, ron)
+    }
+
+    /// load the written outputs, change them and save them if anything changed
+    /// the changes are saved even if `f` fails, as it may have written some outputs
+    pub fn update(f: impl FnOnce(&mut Self) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let loaded = Self::load()?;
+        let mut written = loaded.clone();
+        let res = f(&mut written);
+        if written != loaded {
+            written.save()?;
+        }
+        res
+    }
+
+    /// remove everything the service wrote to an output, and forget it
+    pub fn remove(&mut self, output: &Output) -> anyhow::Result<()> {
+        output.target()?.remove()?;
+        self.outputs.retain(|o| o != output);
+        Ok(())
+    }
+
+    /// write the overrides to an output, recording it first so a partial write is cleaned up too
+    pub fn write(&mut self, output: &Output, overrides: &ColorOverrides) -> anyhow::Result<()> {
+        if !self.outputs.contains(output) {
+            self.outputs.push(output.clone());
+        }
+        output.target()?.write(overrides)
+    }
+}
+
+/// write the overrides to every enabled output, and remove the generated colors from the outputs
+/// which were written before but were removed or disabled since
+/// all outputs are attempted, and the first error is returned
+pub fn write_all(outputs: &[Output], overrides: &ColorOverrides) -> anyhow::Result<()> {
+    Written::update(|written| {
+        let mut res = Ok(());
+        let mut enabled = Vec::new();
+        for output in outputs.iter().filter(|o| o.enabled) {
+            match output.resolved() {
+                Ok(output) => enabled.push(output),
+                Err(e) => {
+                    log::error!("failed to write {:?} output: {e}", output.format);
+                    keep_first_error(&mut res, e);
+                }
+            }
+        }
+        let removed: Vec<_> = written
+            .outputs
+            .iter()
+            .filter(|o| !enabled.contains(o))
+            .cloned()
+            .collect();
+        for output in removed {
+            if let Err(e) = written.remove(&output) {
+                log::error!("failed to remove {:?} output: {e}", output.format);
+                keep_first_error(&mut res, e);
+            }
+        }
+        for output in enabled {
+            if let Err(e) = written.write(&output, overrides) {
+                log::error!("failed to write {:?} output: {e}", output.format);
+                keep_first_error(&mut res, e);
+            }
+        }
+        res
+    })
+}
+
+/// remove the generated colors from the outputs, including disabled ones, and from every output
+/// the service wrote to
+/// all outputs are attempted, and the first error is returned
+pub fn remove_all(outputs: &[Output]) -> anyhow::Result<()> {
+    Written::update(|written| {
+        let mut res = Ok(());
+        let mut removed = written.outputs.clone();
+        for output in outputs {
+            match output.resolved() {
+                Ok(output) if !removed.contains(&output) => removed.push(output),
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("failed to remove {:?} output: {e}", output.format);
+                    keep_first_error(&mut res, e);
+                }
+            }
+        }
+        for output in removed {
+            if let Err(e) = written.remove(&output) {
+                log::error!("failed to remove {:?} output: {e}", output.format);
+                keep_first_error(&mut res, e);
+            }
+        }
+        res
+    })
+}
+
+fn keep_first_error(res: &mut anyhow::Result<()>, e: anyhow::Error) {
+    if res.is_ok() {
+        *res = Err(e);
+    }
+}
+
+pub struct Gtk4Target {
+    pub path: PathBuf,
+}
+
+impl OutputTarget for Gtk4Target {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self, overrides: &ColorOverrides) -> String {
+        let mut css = overrides.as_css(CssMode::Symbolic);
+        css.push_str("@import url(\"custom.css\");\n");
+        css
+    }
+
+    fn write(&self, overrides: &ColorOverrides) -> anyhow::Result<()> {
+        gtk_css::write_block(GTK4_PREFIX, &self.path, &self.render(overrides))
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        gtk_css::remove_block(GTK4_PREFIX, &self.path)
+    }
+}
+
+pub struct Gtk3Target {
+    pub path: PathBuf,
+}
+
+impl OutputTarget for Gtk3Target {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self, overrides: &ColorOverrides) -> String {
+        overrides.as_gtk3_css()
+    }
+
+    fn write(&self, overrides: &ColorOverrides) -> anyhow::Result<()> {
+        gtk_css::write_block(GTK3_PREFIX, &self.path, &self.render(overrides))
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        gtk_css::remove_block(GTK3_PREFIX, &self.path)
+    }
+}
+
+pub struct CssTarget {
+    pub path: PathBuf,
+}
+
+impl OutputTarget for CssTarget {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self, overrides: &ColorOverrides) -> String {
+        format!("{CSS_HEADER}\n{}", overrides.as_css(CssMode::Symbolic))
+    }
+
+    fn write(&self, overrides: &ColorOverrides) -> anyhow::Result<()> {
+        write_generated(&self.path, &self.render(overrides))
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        remove_generated(&self.path, CSS_HEADER)
+    }
+}
+
+pub struct TerminalTarget {
+    pub path: PathBuf,
+}
+
+impl OutputTarget for TerminalTarget {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn render(&self, overrides: &ColorOverrides) -> String {
+        let mut theme = format!("{TERMINAL_HEADER}\n");
+        for (name, key) in TERMINAL_COLORS {
+            let color = match overrides.evaluate(key) {
+                Ok(c) => Color::from(c),
+                // the theme doesn't set the color
+                Err(ColorEvalError::Undefined(k)) if k == *key => continue,
+                Err(e) => {
+                    log::warn!("skipping {name} in the terminal theme, {key} failed: {e}");
+                    continue;
+                }
+            };
+            // terminals only understand opaque hex colors
+            match color.to_hex() {
+                Some(c) => theme.push_str(&format!("{name} {c}\n")),
+                None => log::warn!("skipping {name} in the terminal theme, {key} is not opaque"),
+            }
+        }
+        theme
+    }
+
+    fn write(&self, overrides: &ColorOverrides) -> anyhow::Result<()> {
+        write_generated(&self.path, &self.render(overrides))
+    }
+
+    fn remove(&self) -> anyhow::Result<()> {
+        remove_generated(&self.path, TERMINAL_HEADER)
+    }
+}
+
+fn write_generated(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        atomic::write(path, contents)?;
+    }
+    Ok(())
+}
+
+// only files starting with the header were written by the service
+fn remove_generated(path: &Path, header: &str) -> anyhow::Result<()> {
+    match fs::read_to_string(path) {
+        Ok(c) if c.starts_with(header) => Ok(fs::remove_file(path)?),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_theme() {
+        let target = TerminalTarget {
+            path: PathBuf::from("terminal.conf"),
+        };
+        let mut overrides = ColorOverrides::light_default();
+        let theme = target.render(&overrides);
+        assert!(theme.starts_with(TERMINAL_HEADER));
+        assert!(theme.contains("background #ffffff\n"));
+        assert!(theme.contains("foreground #000000\n"));
+
+        // expressions are evaluated
+        overrides
+            .set_key("view_bg_color", Some("shade(@accent_fg_color, 0)".into()))
+            .unwrap();
+        assert!(target.render(&overrides).contains("background #000000\n"));
+
+        // rgba colors are skipped
+        overrides
+            .set_key("view_fg_color", Some("rgba(0, 0, 0, 0.8)".into()))
+            .unwrap();
+        assert!(!target.render(&overrides).contains("\nforeground "));
+    }
+
+    #[test]
+    fn written_outputs() {
+        let dir =
+            std::env::temp_dir().join(format!("adw-user-colors-output-{}", std::process::id()));
+        let output = Output::new(OutputFormat::Css, Some(dir.join("colors.css")));
+        let overrides = ColorOverrides::light_default();
+
+        let mut written = Written::default();
+        written.write(&output, &overrides).unwrap();
+        written.write(&output, &overrides).unwrap();
+        assert_eq!(written.outputs, std::slice::from_ref(&output));
+        assert!(dir.join("colors.css").exists());
+
+        // outputs are removed even if they aren't configured anymore
+        written.remove(&output).unwrap();
+        assert!(!dir.join("colors.css").exists());
+        assert_eq!(written, Written::default());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::color_scheme::ColorScheme;
+
+/// minutes in a day
+pub const DAY_MINUTES: f64 = 1440.0;
+
+/// switches between the light and dark themes at certain times of the day
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Schedule {
+    /// also set the system color scheme when switching, so applications follow the schedule
+    #[serde(default)]
+    pub set_color_scheme: bool,
+    #[serde(flatten)]
+    pub times: ScheduleTimes,
+}
+
+/// when the light and dark themes become active
+/// written as either `light` and `dark` times, or `latitude` and `longitude` in the schedule
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged, try_from = "RawScheduleTimes")]
+pub enum ScheduleTimes {
+    /// fixed local times, which must differ
+    Fixed { light: TimeOfDay, dark: TimeOfDay },
+    /// sunrise and sunset at the location, in degrees north and east, within ±90 and ±180
+    Sun { latitude: f64, longitude: f64 },
+}
+
+// the schedule times as written, before they are checked
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawScheduleTimes {
+    Fixed { light: TimeOfDay, dark: TimeOfDay },
+    Sun { latitude: f64, longitude: f64 },
+}
+
+impl TryFrom<RawScheduleTimes> for ScheduleTimes {
+    type Error = String;
+
+    fn try_from(raw: RawScheduleTimes) -> Result<Self, Self::Error> {
+        match raw {
+            // there would be no time to switch back
+            RawScheduleTimes::Fixed { light, dark } if light == dark => Err(format!(
+                "the light and dark times of the schedule are both {light}"
+            )),
+            RawScheduleTimes::Fixed { light, dark } => Ok(Self::Fixed { light, dark }),
+            // `contains` is false for NaN as well
+            RawScheduleTimes::Sun { latitude, .. } if !(-90.0..=90.0).contains(&latitude) => Err(
+                format!("the latitude of the schedule is {latitude}, not between -90 and 90"),
+            ),
+            RawScheduleTimes::Sun { longitude, .. } if !(-180.0..=180.0).contains(&longitude) => {
+                Err(format!(
+                    "the longitude of the schedule is {longitude}, not between -180 and 180"
+                ))
+            }
+            RawScheduleTimes::Sun {
+                latitude,
+                longitude,
+            } => Ok(Self::Sun {
+                latitude,
+                longitude,
+            }),
+        }
+    }
+}
+
+/// a local time of the day, written as `HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+/// a point in time, as seen from the local time zone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    /// day of the year, starting at 1
+    pub day_of_year: u32,
+    /// minutes since local midnight
+    pub minutes: f64,
+    /// offset of the local time zone from UTC in minutes
+    pub utc_offset: f64,
+}
+
+/// sunrise and sunset on a day, in minutes since midnight UTC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SunTimes {
+    Times {
+        sunrise: f64,
+        sunset: f64,
+    },
+    /// the sun doesn't set
+    PolarDay,
+    /// the sun doesn't rise
+    PolarNight,
+}
+
+impl Schedule {
+    /// the color scheme the schedule selects at the time
+    /// the light theme is used all day during polar day, and the dark theme during polar night
+    pub fn color_scheme_at(&self, time: &LocalTime) -> ColorScheme {
+        let (light, dark) = match &self.times {
+            ScheduleTimes::Fixed { light, dark } => (light.minutes(), dark.minutes()),
+            ScheduleTimes::Sun {
+                latitude,
+                longitude,
+            } => match sun_times(*latitude, *longitude, time.day_of_year) {
+                SunTimes::Times { sunrise, sunset } => (
+                    (sunrise + time.utc_offset).rem_euclid(DAY_MINUTES),
+                    (sunset + time.utc_offset).rem_euclid(DAY_MINUTES),
+                ),
+                SunTimes::PolarDay => return ColorScheme::Light,
+                SunTimes::PolarNight => return ColorScheme::Dark,
+            },
+        };
+        let m = time.minutes;
+        // the light period may wrap around midnight
+        let is_light = if light <= dark {
+            light <= m && m < dark
+        } else {
+            m >= light || m < dark
+        };
+        if is_light {
+            ColorScheme::Light
+        } else {
+            ColorScheme::Dark
+        }
+    }
+
+    /// the color scheme the schedule selects now
+    pub fn color_scheme(&self) -> ColorScheme {
+        self.color_scheme_at(&LocalTime::now())
+    }
+}
+
+impl TimeOfDay {
+    /// minutes since midnight
+    pub fn minutes(&self) -> f64 {
+        f64::from(self.hour) * 60.0 + f64::from(self.minute)
+    }
+}
+
+impl FromStr for TimeOfDay {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hour, minute) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("expected a time as HH:MM, found {s}"))?;
+        let (hour, minute): (u8, u8) = (hour.trim().parse()?, minute.trim().parse()?);
+        if hour > 23 || minute > 59 {
+            anyhow::bail!("{s} is not a valid time of day");
+        }
+        Ok(Self { hour, minute })
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+impl Serialize for TimeOfDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl LocalTime {
+    /// the current time in the local time zone
+    pub fn now() -> Self {
+        // SAFETY: localtime_r only writes to the tm it is given
+        let tm = unsafe {
+            let t = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&t, &mut tm);
+            tm
+        };
+        Self {
+            day_of_year: tm.tm_yday as u32 + 1,
+            minutes: f64::from(tm.tm_hour * 60 + tm.tm_min) + f64::from(tm.tm_sec) / 60.0,
+            utc_offset: tm.tm_gmtoff as f64 / 60.0,
+        }
+    }
+}
+
+/// compute sunrise and sunset with the NOAA approximation, which is accurate to a few minutes
+pub fn sun_times(latitude: f64, longitude: f64, day_of_year: u32) -> SunTimes {
+    // fractional year at noon, in radians
+    let g = 2.0 * PI / 365.0 * (f64::from(day_of_year) - 1.0);
+    // equation of time in minutes
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * g.cos()
+            - 0.032077 * g.sin()
+            - 0.014615 * (2.0 * g).cos()
+            - 0.040849 * (2.0 * g).sin());
+    // solar declination in radians
+    let decl = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin() - 0.006758 * (2.0 * g).cos()
+        + 0.000907 * (2.0 * g).sin()
+        - 0.002697 * (3.0 * g).cos()
+        + 0.00148 * (3.0 * g).sin();
+
+    // the sun is 0.833° below the horizon at sunrise, accounting for refraction and its size
+    let lat = latitude.to_radians();
+    let cos_ha = 90.833f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    if cos_ha > 1.0 {
+        return SunTimes::PolarNight;
+    }
+    if cos_ha < -1.0 {
+        return SunTimes::PolarDay;
+    }
+    let ha = cos_ha.acos().to_degrees();
+    SunTimes::Times {
+        sunrise: (720.0 - 4.0 * (longitude + ha) - eqtime).rem_euclid(DAY_MINUTES),
+        sunset: (720.0 - 4.0 * (longitude - ha) - eqtime).rem_euclid(DAY_MINUTES),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day_of_year: u32, time: &str, utc_offset: f64) -> LocalTime {
+        LocalTime {
+            day_of_year,
+            minutes: time.parse::<TimeOfDay>().unwrap().minutes(),
+            utc_offset,
+        }
+    }
+
+    #[test]
+    fn fixed_times() {
+        let schedule = |light: &str, dark: &str| Schedule {
+            set_color_scheme: false,
+            times: ScheduleTimes::Fixed {
+                light: light.parse().unwrap(),
+                dark: dark.parse().unwrap(),
+            },
+        };
+        let day = schedule("07:00", "19:30");
+        assert_eq!(day.color_scheme_at(&at(1, "06:59", 0.0)), ColorScheme::Dark);
+        assert_eq!(
+            day.color_scheme_at(&at(1, "07:00", 0.0)),
+            ColorScheme::Light
+        );
+        assert_eq!(day.color_scheme_at(&at(1, "19:30", 0.0)), ColorScheme::Dark);
+
+        // light over midnight
+        let night = schedule("22:00", "02:00");
+        assert_eq!(
+            night.color_scheme_at(&at(1, "23:00", 0.0)),
+            ColorScheme::Light
+        );
+        assert_eq!(
+            night.color_scheme_at(&at(1, "01:00", 0.0)),
+            ColorScheme::Light
+        );
+        assert_eq!(
+            night.color_scheme_at(&at(1, "12:00", 0.0)),
+            ColorScheme::Dark
+        );
+
+        let parsed: Schedule = toml::from_str("light = '07:00'\ndark = '19:30'\n").unwrap();
+        assert_eq!(parsed, day);
+        // the same time twice would never switch back
+        assert!(toml::from_str::<Schedule>("light = '07:00'\ndark = '07:00'\n").is_err());
+
+        assert!("24:00".parse::<TimeOfDay>().is_err());
+        assert!("7".parse::<TimeOfDay>().is_err());
+        assert_eq!("7:05".parse::<TimeOfDay>().unwrap().to_string(), "07:05");
+    }
+
+    #[test]
+    fn sunrise_and_sunset() {
+        let close = |a: f64, b: &str| (a - b.parse::<TimeOfDay>().unwrap().minutes()).abs() < 5.0;
+
+        // Berlin at the summer solstice, 02:43 and 19:33 UTC
+        match sun_times(52.52, 13.405, 172) {
+            SunTimes::Times { sunrise, sunset } => {
+                assert!(close(sunrise, "02:43"), "{sunrise}");
+                assert!(close(sunset, "19:33"), "{sunset}");
+            }
+            t => panic!("{t:?}"),
+        }
+        // Sydney in early January, 18:49 and 09:09 UTC, wrapping around midnight
+        match sun_times(-33.87, 151.21, 5) {
+            SunTimes::Times { sunrise, sunset } => {
+                assert!(close(sunrise, "18:49"), "{sunrise}");
+                assert!(close(sunset, "09:09"), "{sunset}");
+            }
+            t => panic!("{t:?}"),
+        }
+        // Tromsø
+        assert_eq!(sun_times(69.65, 18.96, 172), SunTimes::PolarDay);
+        assert_eq!(sun_times(69.65, 18.96, 355), SunTimes::PolarNight);
+
+        let berlin = Schedule {
+            set_color_scheme: false,
+            times: ScheduleTimes::Sun {
+                latitude: 52.52,
+                longitude: 13.405,
+            },
+        };
+        // CEST
+        assert_eq!(
+            berlin.color_scheme_at(&at(172, "12:00", 120.0)),
+            ColorScheme::Light
+        );
+        assert_eq!(
+            berlin.color_scheme_at(&at(172, "22:00", 120.0)),
+            ColorScheme::Dark
+        );
+        assert_eq!(
+            berlin.color_scheme_at(&at(172, "04:30", 120.0)),
+            ColorScheme::Dark
+        );
+        assert_eq!(
+            berlin.color_scheme_at(&at(172, "05:00", 120.0)),
+            ColorScheme::Light
+        );
+        // CET, sunset at about 16:00
+        assert_eq!(
+            berlin.color_scheme_at(&at(355, "16:30", 60.0)),
+            ColorScheme::Dark
+        );
+    }
+
+    #[test]
+    fn invalid_location() {
+        let parse = |s: &str| toml::from_str::<Schedule>(s);
+        assert!(parse("latitude = 90.0\nlongitude = -180.0\n").is_ok());
+        assert!(parse("latitude = 90.5\nlongitude = 0.0\n").is_err());
+        assert!(parse("latitude = 0.0\nlongitude = -181.0\n").is_err());
+        // a NaN location would always be dark
+        assert!(parse("latitude = nan\nlongitude = 0.0\n").is_err());
+        assert!(parse("latitude = 0.0\nlongitude = inf\n").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+pub use sd_notify::NotifyState;
+
+use crate::config::Config;
+
+/// send state changes to the service manager
+/// nothing is sent if the service wasn't started with `Type=notify`
+pub fn notify(states: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, states) {
+        log::warn!("failed to notify the service manager: {e}");
+    }
+}
+
+/// status line describing the selected themes, and the result of applying the active one
+pub fn status(config: &Config, active: &str, res: &anyhow::Result<()>) -> String {
+    let name = |n: &str| {
+        if n.is_empty() {
+            "none".to_string()
+        } else {
+            n.to_string()
+        }
+    };
+    let status = format!(
+        "light: {}, dark: {}, active: {}",
+        name(&config.light),
+        name(&config.dark),
+        name(active)
+    );
+    match res {
+        Ok(()) => status,
+        Err(e) => format!("{status} (failed: {e})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_line() {
+        let config = Config::new("red".into(), String::new());
+        assert_eq!(
+            status(&config, "red", &Ok(())),
+            "light: red, dark: none, active: red"
+        );
+        assert_eq!(
+            status(&config, "", &Err(anyhow::anyhow!("no theme"))),
+            "light: red, dark: none, active: none (failed: no theme)"
+        );
+    }
+}
@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: MPL-2.0-only
+
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
+
+use adw::glib::{self, SourceId};
+use futures::channel::mpsc::UnboundedSender;
+use notify::{event::ModifyKind, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{atomic, colors::ColorOverrides, config::Config};
+
+/// time to wait for more file events before the theme is applied
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+/// time to wait before retrying to watch the directories
+pub const RETRY: Duration = Duration::from_secs(5);
+
+/// the files whose changes affect the rendered theme
+#[derive(Debug, Clone, Default)]
+pub struct WatchFilter {
+    pub config_path: PathBuf,
+    /// names of the active light and dark themes of the desktop and of applications, and of the
+    /// themes they inherit from
+    pub themes: HashSet<String>,
+}
+
+impl WatchFilter {
+    /// filter for the config file and the themes it selects
+    pub fn new(config_path: PathBuf, config: &Config) -> Self {
+        let mut themes = HashSet::new();
+        let app_themes = config
+            .apps
+            .values()
+            .flat_map(|t| t.light.iter().chain(t.dark.iter()));
+        for name in [&config.light, &config.dark].into_iter().chain(app_themes) {
+            let mut next = Some(name.clone());
+            // stops at missing themes and inheritance cycles
+            while let Some(name) = next.take() {
+                if name.is_empty() || !themes.insert(name.clone()) {
+                    break;
+                }
+                next = ColorOverrides::load_from_name(&name)
+                    .ok()
+                    .and_then(|t| t.base);
+            }
+        }
+        Self {
+            config_path,
+            themes,
+        }
+    }
+
+    /// filter for the current config
+    pub fn load() -> anyhow::Result<Self> {
+        Ok(Self::new(Config::path()?, &Config::load()?))
+    }
+
+    /// check whether the event changed the config or one of the active theme files
+    /// temporary files of atomic writes are ignored, the rename to the destination matches
+    pub fn matches(&self, event: &Event) -> bool {
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return false;
+        }
+        event
+            .paths
+            .iter()
+            .filter(|p| !atomic::is_temp_file(p))
+            .any(|p| {
+                if *p == self.config_path {
+                    return true;
+                }
+                let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+                p.extension() == Some("ron".as_ref()) && self.themes.contains(stem)
+            })
+    }
+}
+
+/// watches the config and theme directories, recovering when they are removed or replaced
+pub struct DirWatcher {
+    watcher: RecommendedWatcher,
+    dirs: Vec<PathBuf>,
+}
+
+impl DirWatcher {
+    /// create a watcher sending its events to `tx`, the directories are watched by [`Self::arm`]
+    pub fn new(
+        dirs: Vec<PathBuf>,
+        tx: UnboundedSender<notify::Result<Event>>,
+    ) -> anyhow::Result<Self> {
+        // the receiver is only gone when the service is shutting down
+        let watcher = RecommendedWatcher::new(move |res| {
+            let _ = tx.unbounded_send(res);
+        })?;
+        Ok(Self { watcher, dirs })
+    }
+
+    /// create a watcher, retrying until it succeeds
+    pub async fn new_retrying(
+        dirs: Vec<PathBuf>,
+        tx: UnboundedSender<notify::Result<Event>>,
+    ) -> Self {
+        loop {
+            match Self::new(dirs.clone(), tx.clone()) {
+                Ok(watcher) => return watcher,
+                Err(e) => {
+                    log::error!("failed to create file watcher, retrying in {RETRY:?}: {e}");
+                    glib::timeout_future(RETRY).await;
+                }
+            }
+        }
+    }
+
+    /// create the directories if they are missing, and watch them
+    /// existing watches are replaced, so this also re-arms watches of replaced directories
+    pub fn try_arm(&mut self) -> anyhow::Result<()> {
+        for dir in &self.dirs {
+            // the old watch is gone with the directory it was on
+            let _ = self.watcher.unwatch(dir);
+            fs::create_dir_all(dir)?;
+            self.watcher.watch(dir, RecursiveMode::Recursive)?;
+            log::debug!("watching {}", dir.display());
+        }
+        Ok(())
+    }
+
+    /// watch the directories, retrying until it succeeds
+    pub async fn arm(&mut self) {
+        while let Err(e) = self.try_arm() {
+            log::error!("failed to watch directories, retrying in {RETRY:?}: {e}");
+            glib::timeout_future(RETRY).await;
+        }
+    }
+
+    /// check whether the event removed or moved one of the watched directories
+    pub fn is_dir_lost(&self, event: &Event) -> bool {
+        matches!(
+            event.kind,
+            EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+        ) && event.paths.iter().any(|p| self.is_watched_dir(p))
+    }
+
+    fn is_watched_dir(&self, path: &Path) -> bool {
+        self.dirs.iter().any(|d| d == path)
+    }
+}
+
+/// coalesces calls made in quick succession into a single call of the callback
+/// the callback runs on the default main context once no calls were made for the delay
+pub struct Debouncer {
+    delay: Duration,
+    pending: Rc<RefCell<Option<SourceId>>>,
+    callback: Rc<dyn Fn()>,
+}
+
+impl Debouncer {
+    pub fn new(delay: Duration, callback: impl Fn() + 'static) -> Self {
+        Self {
+            delay,
+            pending: Default::default(),
+            callback: Rc::new(callback),
+        }
+    }
+
+    /// schedule the callback, postponing it if it is already scheduled
+    pub fn call(&self) {
+        if let Some(id) = self.pending.borrow_mut().take() {
+            id.remove();
+        }
+        let pending = self.pending.clone();
+        let callback = self.callback.clone();
+        let id = glib::timeout_add_local_once(self.delay, move || {
+            pending.borrow_mut().take();
+            callback();
+        });
+        *self.pending.borrow_mut() = Some(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+
+    use super::*;
+
+    #[test]
+    fn filter_active_files() {
+        let filter = WatchFilter {
+            config_path: PathBuf::from("/config/adwaita-user-colors/config.ron.toml"),
+            themes: HashSet::from(["red".to_string(), "blue".to_string()]),
+        };
+        let event = |kind, path: &str| Event::new(kind).add_path(PathBuf::from(path));
+        let modify = || EventKind::Modify(ModifyKind::Any);
+
+        assert!(filter.matches(&event(
+            modify(),
+            "/config/adwaita-user-colors/config.ron.toml"
+        )));
+        assert!(filter.matches(&event(modify(), "/data/color-overrides/red.ron")));
+        assert!(filter.matches(&event(
+            EventKind::Create(CreateKind::File),
+            "/data/color-overrides/blue.ron"
+        )));
+        assert!(!filter.matches(&event(modify(), "/data/color-overrides/green.ron")));
+        assert!(!filter.matches(&event(modify(), "/data/color-overrides/red.ron~")));
+        assert!(!filter.matches(&event(
+            modify(),
+            "/data/color-overrides/.adw-user-colors-red.ron.42.0.tmp"
+        )));
+        assert!(!filter.matches(&event(
+            EventKind::Access(AccessKind::Any),
+            "/data/color-overrides/red.ron"
+        )));
+    }
+}
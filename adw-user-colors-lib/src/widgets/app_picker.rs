@@ -1 +1,188 @@
 // SPDX-License-Identifier: MPL-2.0-only
+
+use adw::{
+    glib::{self, closure_local},
+    gtk::{self, gio, prelude::*, subclass::prelude::*},
+    prelude::*,
+    ActionRow,
+};
+
+use crate::apps;
+
+mod imp {
+    use adw::{
+        glib::{self, once_cell::sync::Lazy, subclass::Signal},
+        gtk::{self, prelude::*, subclass::prelude::*},
+    };
+    use std::cell::RefCell;
+
+    // Object holding the state
+    #[derive(Default)]
+    pub struct AppPicker {
+        pub search: RefCell<gtk::SearchEntry>,
+        pub list: RefCell<gtk::ListBox>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for AppPicker {
+        const NAME: &'static str = "AdwUserColorsAppPicker";
+        type Type = super::AppPicker;
+        type ParentType = gtk::Box;
+    }
+
+    // Trait shared by all GObjects
+    impl ObjectImpl for AppPicker {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder(
+                    // Signal name
+                    "app-selected",
+                    // the application ID
+                    &[String::static_type().into()],
+                    // Type of the value the signal handler sends back
+                    <()>::static_type().into(),
+                )
+                .build()]
+            });
+            SIGNALS.as_ref()
+        }
+    }
+
+    // Trait shared by all widgets
+    impl WidgetImpl for AppPicker {}
+
+    // Trait shared by all boxes
+    impl BoxImpl for AppPicker {}
+}
+
+glib::wrapper! {
+    /// searchable list of the installed flatpak applications, emitting `app-selected` with the
+    /// application ID when one is activated
+    pub struct AppPicker(ObjectSubclass<imp::AppPicker>)
+        @extends gtk::Box, gtk::Widget,
+    @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget, gtk::Orientable;
+}
+
+impl Default for AppPicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AppPicker {
+    pub fn new() -> Self {
+        let self_: Self = glib::Object::new(&[]).expect("Failed to create `AppPicker`.");
+        self_.set_orientation(gtk::Orientation::Vertical);
+        self_.set_spacing(4);
+
+        let search = gtk::SearchEntry::new();
+        search.set_placeholder_text(Some("Search Applications"));
+        let list = gtk::ListBox::new();
+        list.add_css_class("boxed-list");
+        list.set_selection_mode(gtk::SelectionMode::None);
+        for (id, app) in installed_apps() {
+            list.append(&app_row(&id, &app));
+        }
+
+        let scroll_window = gtk::ScrolledWindow::builder()
+            .hexpand(true)
+            .vexpand(true)
+            .child(&list)
+            .build();
+        self_.append(&search);
+        self_.append(&scroll_window);
+
+        let imp = imp::AppPicker::from_instance(&self_);
+        imp.search.replace(search);
+        imp.list.replace(list);
+
+        self_.connect_search();
+        self_.connect_list();
+
+        self_
+    }
+
+    /// call `f` with the application ID when an application is selected
+    pub fn connect_app_selected<F: Fn(&Self, String) + 'static>(&self, f: F) {
+        self.connect_closure(
+            "app-selected",
+            false,
+            closure_local!(move |self_: &Self, app_id: String| f(self_, app_id)),
+        );
+    }
+
+    fn connect_search(&self) {
+        let imp = imp::AppPicker::from_instance(self);
+        let list = imp.list.borrow().clone();
+        let search = imp.search.borrow().clone();
+        list.set_filter_func(
+            glib::clone!(@weak search => @default-return true, move |row| {
+                let query = search.text().to_lowercase();
+                match row.downcast_ref::<ActionRow>() {
+                    Some(row) => {
+                        row.title().to_lowercase().contains(&query)
+                            || row
+                                .subtitle()
+                                .map(|s| s.to_lowercase().contains(&query))
+                                .unwrap_or_default()
+                    }
+                    None => true,
+                }
+            }),
+        );
+        search.connect_search_changed(glib::clone!(@weak list => move |_| {
+            list.invalidate_filter();
+        }));
+    }
+
+    fn connect_list(&self) {
+        let imp = imp::AppPicker::from_instance(self);
+        imp.list.borrow().connect_row_activated(
+            glib::clone!(@weak self as self_ => move |_, row| {
+                if let Some(app_id) = row.downcast_ref::<ActionRow>().and_then(|r| r.subtitle()) {
+                    self_.emit_by_name::<()>("app-selected", &[&app_id.to_string()]);
+                }
+            }),
+        );
+    }
+}
+
+// flatpak applications shown in menus, by application ID
+// only flatpak applications read the stylesheet written for them, so host applications are left
+// out
+fn installed_apps() -> Vec<(String, gio::AppInfo)> {
+    let flatpak_ids = match apps::flatpak_app_ids() {
+        Ok(ids) => ids,
+        Err(e) => {
+            log::error!("failed to list flatpak applications: {e}");
+            return Vec::new();
+        }
+    };
+    let mut apps: Vec<_> = gio::AppInfo::all()
+        .into_iter()
+        .filter(|app| app.should_show())
+        .filter_map(|app| {
+            let id = app.id()?;
+            let id = id.strip_suffix(".desktop").unwrap_or(&id).to_string();
+            flatpak_ids.contains(&id).then_some((id, app))
+        })
+        .collect();
+    apps.sort_by_cached_key(|(_, app)| app.display_name().to_lowercase());
+    apps.dedup_by(|(a, _), (b, _)| a == b);
+    apps
+}
+
+fn app_row(app_id: &str, app: &gio::AppInfo) -> ActionRow {
+    let row = ActionRow::builder()
+        .title(&app.display_name())
+        .subtitle(app_id)
+        .activatable(true)
+        .build();
+    let icon = match app.icon() {
+        Some(icon) => gtk::Image::from_gicon(&icon),
+        None => gtk::Image::from_icon_name("application-x-executable"),
+    };
+    icon.set_icon_size(gtk::IconSize::Large);
+    row.add_prefix(&icon);
+    row
+}
@@ -1 +1,7 @@
 // SPDX-License-Identifier: MPL-2.0-only
+
+pub mod app_picker;
+pub mod system_picker;
+
+pub use app_picker::AppPicker;
+pub use system_picker::SystemPicker;
@@ -1 +1,257 @@
 // SPDX-License-Identifier: MPL-2.0-only
+
+use std::f64::consts::PI;
+
+use adw::{
+    glib::{self, closure_local},
+    gtk::{self, prelude::*, subclass::prelude::*},
+    prelude::*,
+    ActionRow,
+};
+
+use crate::{
+    color_scheme::ColorScheme,
+    colors::ColorOverrides,
+    config::Config,
+    library::{ThemeEntry, ThemeLibrary, ThemeSource},
+};
+
+/// colors shown in the swatch of a theme
+pub const SWATCH_COLORS: &[&str] = &["window_bg_color", "view_bg_color", "accent_bg_color"];
+const SWATCH_SIZE: i32 = 16;
+const SWATCH_SPACING: i32 = 4;
+
+mod imp {
+    use adw::{
+        glib::{self, once_cell::sync::Lazy, subclass::Signal},
+        gtk::{self, prelude::*, subclass::prelude::*},
+    };
+    use std::cell::RefCell;
+
+    // Object holding the state
+    #[derive(Default)]
+    pub struct SystemPicker {
+        pub light: RefCell<gtk::ListBox>,
+        pub dark: RefCell<gtk::ListBox>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for SystemPicker {
+        const NAME: &'static str = "AdwUserColorsSystemPicker";
+        type Type = super::SystemPicker;
+        type ParentType = gtk::Box;
+    }
+
+    // Trait shared by all GObjects
+    impl ObjectImpl for SystemPicker {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                // the name of the selected theme
+                ["light-theme-selected", "dark-theme-selected"]
+                    .into_iter()
+                    .map(|name| {
+                        Signal::builder(
+                            name,
+                            &[String::static_type().into()],
+                            <()>::static_type().into(),
+                        )
+                        .build()
+                    })
+                    .collect()
+            });
+            SIGNALS.as_ref()
+        }
+    }
+
+    // Trait shared by all widgets
+    impl WidgetImpl for SystemPicker {}
+
+    // Trait shared by all boxes
+    impl BoxImpl for SystemPicker {}
+}
+
+glib::wrapper! {
+    /// lists the installed themes with swatches of their colors, selecting the light and dark
+    /// themes of the desktop
+    /// the selection is saved to the config, then `light-theme-selected` or
+    /// `dark-theme-selected` is emitted with the name of the theme
+    pub struct SystemPicker(ObjectSubclass<imp::SystemPicker>)
+        @extends gtk::Box, gtk::Widget,
+    @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget, gtk::Orientable;
+}
+
+impl Default for SystemPicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemPicker {
+    pub fn new() -> Self {
+        let self_: Self = glib::Object::new(&[]).expect("Failed to create `SystemPicker`.");
+        self_.set_orientation(gtk::Orientation::Vertical);
+        self_.set_spacing(8);
+
+        let imp = imp::SystemPicker::from_instance(&self_);
+        for (title, list) in [("Light Theme", &imp.light), ("Dark Theme", &imp.dark)] {
+            let label = gtk::Label::new(Some(title));
+            label.set_halign(gtk::Align::Start);
+            label.add_css_class("heading");
+            let list_box = gtk::ListBox::new();
+            list_box.set_selection_mode(gtk::SelectionMode::None);
+            list_box.add_css_class("boxed-list");
+            self_.append(&label);
+            self_.append(&list_box);
+            list.replace(list_box);
+        }
+        self_.reload();
+
+        self_
+    }
+
+    /// list the installed themes and the selected ones again, eg. after themes were added
+    /// the picker is insensitive while the config fails to load, so it isn't overwritten
+    pub fn reload(&self) {
+        let imp = imp::SystemPicker::from_instance(self);
+        let config = match Config::load() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                log::error!("failed to load config: {e}");
+                None
+            }
+        };
+        self.set_sensitive(config.is_some());
+        let entries = ThemeLibrary::new()
+            .and_then(|library| library.list())
+            .unwrap_or_else(|e| {
+                log::error!("failed to list themes: {e}");
+                Vec::new()
+            });
+        for (color_scheme, list) in [
+            (ColorScheme::Light, imp.light.borrow()),
+            (ColorScheme::Dark, imp.dark.borrow()),
+        ] {
+            while let Some(row) = list.first_child() {
+                list.remove(&row);
+            }
+            let selected = config
+                .as_ref()
+                .map(|c| c.theme_name(color_scheme))
+                .unwrap_or_default();
+            let mut group: Option<gtk::CheckButton> = None;
+            for entry in &entries {
+                let check = gtk::CheckButton::new();
+                check.set_group(group.as_ref());
+                // set before connecting, so loading the selection doesn't save it again
+                check.set_active(entry.name == selected);
+                self.connect_check(&check, color_scheme, &entry.name);
+                list.append(&theme_row(entry, &entries, color_scheme, &check));
+                group.get_or_insert(check);
+            }
+        }
+    }
+
+    /// call `f` with the name of the theme when a light theme is selected
+    pub fn connect_light_theme_selected<F: Fn(&Self, String) + 'static>(&self, f: F) {
+        self.connect_closure(
+            "light-theme-selected",
+            false,
+            closure_local!(move |self_: &Self, name: String| f(self_, name)),
+        );
+    }
+
+    /// call `f` with the name of the theme when a dark theme is selected
+    pub fn connect_dark_theme_selected<F: Fn(&Self, String) + 'static>(&self, f: F) {
+        self.connect_closure(
+            "dark-theme-selected",
+            false,
+            closure_local!(move |self_: &Self, name: String| f(self_, name)),
+        );
+    }
+
+    fn connect_check(&self, check: &gtk::CheckButton, color_scheme: ColorScheme, name: &str) {
+        let name = name.to_string();
+        check.connect_toggled(glib::clone!(@weak self as self_ => move |check| {
+            if !check.is_active() {
+                return;
+            }
+            let (res, signal) = match color_scheme {
+                ColorScheme::Light => (Config::set_active_light(&name), "light-theme-selected"),
+                ColorScheme::Dark => (Config::set_active_dark(&name), "dark-theme-selected"),
+            };
+            match res {
+                Ok(()) => self_.emit_by_name::<()>(signal, &[&name]),
+                Err(e) => log::error!("failed to select {name}: {e}"),
+            }
+        }));
+    }
+}
+
+// row selecting a theme for the color scheme, system themes show their pack
+fn theme_row(
+    entry: &ThemeEntry,
+    entries: &[ThemeEntry],
+    color_scheme: ColorScheme,
+    check: &gtk::CheckButton,
+) -> ActionRow {
+    let row = ActionRow::builder().title(&entry.name).build();
+    match (entry.source, &entry.pack) {
+        (ThemeSource::System, Some(pack)) => row.set_subtitle(&format!("System theme from {pack}")),
+        (ThemeSource::System, None) => row.set_subtitle("System theme"),
+        _ => {}
+    }
+    row.add_prefix(&swatch(entry, entries, color_scheme));
+    row.add_suffix(check);
+    row.set_activatable_widget(Some(check));
+    row
+}
+
+// circles filled with the colors of the theme, on top of the defaults of the color scheme
+// base themes are looked up in the listed themes, instead of searching the theme directories again
+fn swatch(
+    entry: &ThemeEntry,
+    entries: &[ThemeEntry],
+    color_scheme: ColorScheme,
+) -> gtk::DrawingArea {
+    let default = match color_scheme {
+        ColorScheme::Light => ColorOverrides::light_default(),
+        ColorScheme::Dark => ColorOverrides::dark_default(),
+    };
+    let mut load_base = |name: &str| match entries.iter().find(|e| e.name == name) {
+        Some(e) => e.load(),
+        None => anyhow::bail!("Could not find theme {name}"),
+    };
+    let theme = entry
+        .load()
+        .and_then(|t| t.resolve_with(&mut load_base))
+        .map(|t| ColorOverrides::merge(&default, &t))
+        .unwrap_or_else(|e| {
+            log::warn!("failed to load {}: {e}", entry.name);
+            default
+        });
+    let colors: Vec<_> = SWATCH_COLORS
+        .iter()
+        .filter_map(|key| theme.evaluate(key).ok())
+        .collect();
+
+    let area = gtk::DrawingArea::new();
+    area.set_content_width(
+        SWATCH_COLORS.len() as i32 * (SWATCH_SIZE + SWATCH_SPACING) - SWATCH_SPACING,
+    );
+    area.set_content_height(SWATCH_SIZE);
+    area.set_valign(gtk::Align::Center);
+    area.set_draw_func(move |_, cr, _, height| {
+        let r = f64::from(SWATCH_SIZE) / 2.0;
+        for (i, c) in colors.iter().enumerate() {
+            let x = f64::from(i as i32 * (SWATCH_SIZE + SWATCH_SPACING)) + r;
+            cr.arc(x, f64::from(height) / 2.0, r - 0.5, 0.0, 2.0 * PI);
+            cr.set_source_rgba(c.red.into(), c.green.into(), c.blue.into(), c.alpha.into());
+            let _ = cr.fill_preserve();
+            // outline, so colors close to the background stay visible
+            cr.set_source_rgba(0.5, 0.5, 0.5, 0.5);
+            cr.set_line_width(1.0);
+            let _ = cr.stroke();
+        }
+    });
+    area
+}
@@ -1,16 +1,520 @@
 // SPDX-License-Identifier: MPL-2.0-only
 
-pub fn main() -> anyhow::Result<()> {
-    if let Some(arg) = std::env::args().nth(1) {
-        if arg == "--start" {
-            user_colors::load()?;
-        } else if arg == "--stop" {
-            user_colors::unload()?;
-        } else {
-            println!("Usage: adw-user-colors --start OR adw-user-colors --stop")
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use clap::{CommandFactory, ErrorKind, Parser, Subcommand};
+use log::LevelFilter;
+use serde_json::{json, Value};
+use user_colors::{
+    atomic,
+    color_scheme::ColorScheme,
+    colors::{ColorOverrides, CssMode},
+    config::Config,
+    dbus,
+    library::{ThemeLibrary, ThemeSource},
+};
+
+/// Apply user color overrides to libadwaita and GTK 3 applications
+#[derive(Parser)]
+#[clap(name = "adw-user-colors", version, arg_required_else_help = true)]
+struct Cli {
+    /// Print machine-readable JSON
+    #[clap(long, global = true)]
+    json: bool,
+    // kept for units installed by older versions
+    #[clap(long, hide = true, conflicts_with = "stop")]
+    start: bool,
+    #[clap(long, hide = true)]
+    stop: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the service, applying the active theme whenever it changes
+    Start,
+    /// Stop the running service and remove the generated colors
+    Stop,
+    /// List the available themes
+    List,
+    /// Show the colors of a theme
+    Show {
+        name: String,
+        /// Include the colors inherited from base themes
+        #[clap(long)]
+        resolved: bool,
+    },
+    /// Select the theme used in light mode
+    SetLight { name: String },
+    /// Select the theme used in dark mode
+    SetDark { name: String },
+    /// Apply the active theme
+    Apply {
+        /// Render the outputs once in this process and exit, instead of asking the running
+        /// service
+        #[clap(long)]
+        once: bool,
+        /// Apply the light theme instead of detecting the color scheme
+        #[clap(long, requires = "once", conflicts_with = "dark")]
+        light: bool,
+        /// Apply the dark theme instead of detecting the color scheme
+        #[clap(long, requires = "once")]
+        dark: bool,
+    },
+    /// Write a theme to a file, or to stdout
+    Export {
+        name: String,
+        /// File to write the theme to
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Export the generated css instead of the theme
+        #[clap(long)]
+        css: bool,
+    },
+    /// Add a theme file to the user's themes
+    Import {
+        file: PathBuf,
+        /// Name of the imported theme, defaults to the name in the file
+        #[clap(long)]
+        name: Option<String>,
+        /// Replace an existing theme with the same name
+        #[clap(long)]
+        force: bool,
+    },
+    /// Rename one of the user's themes, updating the themes and config referring to it
+    Rename { name: String, new_name: String },
+    /// Copy a theme to the user's themes under a new name
+    Duplicate { name: String, new_name: String },
+    /// Delete one of the user's themes
+    Delete { name: String },
+    /// Copy a system or built-in theme to the user's themes, so it can be changed
+    Copy { name: String },
+    /// Check theme files or theme names for errors
+    Validate {
+        #[clap(required = true)]
+        themes: Vec<String>,
+    },
+    /// Show the configuration and whether the service is running
+    Status,
+}
+
+pub fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let command = match (&cli.command, cli.start, cli.stop) {
+        (Some(_), true, _) | (Some(_), _, true) => Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--start and --stop can't be combined with a subcommand",
+            )
+            .exit(),
+        (Some(command), _, _) => command,
+        (None, true, _) => &Command::Start,
+        (None, _, true) => &Command::Stop,
+        // clap only prints the help if there are no arguments at all, not with just `--json`
+        (None, false, false) => {
+            let _ = Cli::command().print_help();
+            return ExitCode::from(2);
+        }
+    };
+    init_logger(command);
+    match run(command, cli.json) {
+        Ok(code) => code,
+        Err(e) => {
+            if cli.json {
+                print_json(json!({ "error": format!("{e:#}") }));
+            } else {
+                eprintln!("error: {e:#}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: &Command, json: bool) -> anyhow::Result<ExitCode> {
+    match command {
+        Command::Start => user_colors::load()?,
+        Command::Stop => user_colors::unload()?,
+        Command::List => list(json)?,
+        Command::Show { name, resolved } => show(name, *resolved, json)?,
+        Command::SetLight { name } => set_active(name, ColorScheme::Light, json)?,
+        Command::SetDark { name } => set_active(name, ColorScheme::Dark, json)?,
+        Command::Apply { once, light, dark } => {
+            let color_scheme = match (light, dark) {
+                (true, _) => Some(ColorScheme::Light),
+                (_, true) => Some(ColorScheme::Dark),
+                _ => None,
+            };
+            apply(*once, color_scheme, json)?
+        }
+        Command::Export { name, output, css } => export(name, output.as_ref(), *css, json)?,
+        Command::Import { file, name, force } => import(file, name.as_deref(), *force, json)?,
+        Command::Rename { name, new_name } => rename(name, new_name, json)?,
+        Command::Duplicate { name, new_name } => duplicate(name, new_name, json)?,
+        Command::Delete { name } => delete(name, json)?,
+        Command::Copy { name } => copy(name, json)?,
+        Command::Validate { themes } => return validate(themes, json),
+        Command::Status => status(json)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+// the service reports what it does, other commands only report problems
+// RUST_LOG overrides the defaults
+fn init_logger(command: &Command) {
+    let mut builder = pretty_env_logger::formatted_builder();
+    builder.filter_level(match command {
+        Command::Start => LevelFilter::Info,
+        _ => LevelFilter::Warn,
+    });
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+}
+
+fn print_json(value: Value) {
+    println!("{}", serde_json::to_string_pretty(&value).unwrap());
+}
+
+fn list(json: bool) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let themes: Vec<_> = ThemeLibrary::new()?
+        .list()?
+        .into_iter()
+        .map(|theme| {
+            json!({
+                "name": theme.name,
+                "source": source_name(theme.source),
+                "path": theme.path,
+                "pack": theme.pack,
+                "read_only": theme.is_read_only(),
+                "builtin": theme.source == ThemeSource::Builtin,
+                "light": theme.name == config.light,
+                "dark": theme.name == config.dark,
+            })
+        })
+        .collect();
+    if json {
+        print_json(Value::Array(themes));
+        return Ok(());
+    }
+    for theme in themes {
+        let mut line = theme["name"].as_str().unwrap_or_default().to_string();
+        match theme["pack"].as_str() {
+            Some(pack) => line.push_str(&format!(" (system: {pack})")),
+            None if theme["source"] == "system" => line.push_str(" (system)"),
+            None => {}
+        }
+        for marker in ["builtin", "light", "dark"] {
+            if theme[marker] == true {
+                line.push_str(&format!(" ({marker})"));
+            }
+        }
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn source_name(source: ThemeSource) -> &'static str {
+    match source {
+        ThemeSource::User => "user",
+        ThemeSource::System => "system",
+        ThemeSource::Builtin => "builtin",
+    }
+}
+
+fn show(name: &str, resolved: bool, json: bool) -> anyhow::Result<()> {
+    let mut theme = ColorOverrides::load_from_name(name)?;
+    if resolved {
+        theme = theme.resolve()?;
+    }
+    if json {
+        let colors: serde_json::Map<_, _> = theme
+            .iter()
+            .map(|(key, color)| (key.name.to_string(), Value::String(color.to_string())))
+            .collect();
+        print_json(json!({
+            "name": theme.name,
+            "base": theme.base,
+            "version": theme.version,
+            "colors": colors,
+        }));
+        return Ok(());
+    }
+    println!("name: {}", theme.name);
+    if let Some(base) = &theme.base {
+        println!("base: {base}");
+    }
+    for (key, color) in theme.iter() {
+        println!("{}: {color}", key.name);
+    }
+    Ok(())
+}
+
+fn set_active(name: &str, color_scheme: ColorScheme, json: bool) -> anyhow::Result<()> {
+    // refuse themes the service would fail to render
+    ColorOverrides::load_from_name(name)?.resolve()?;
+    let mode = match color_scheme {
+        ColorScheme::Light => {
+            Config::set_active_light(name)?;
+            "light"
+        }
+        ColorScheme::Dark => {
+            Config::set_active_dark(name)?;
+            "dark"
         }
+    };
+    if json {
+        print_json(json!({ mode: name }));
     } else {
-        println!("Usage: adw-user-colors --start OR adw-user-colors --stop")
+        println!("{mode} theme set to {name}");
+    }
+    Ok(())
+}
+
+fn apply(once: bool, color_scheme: Option<ColorScheme>, json: bool) -> anyhow::Result<()> {
+    let name = if once {
+        user_colors::apply(color_scheme)?
+    } else {
+        dbus::call("Reload", None)
+            .map_err(|e| anyhow::anyhow!("failed to reach the running service: {e}"))?;
+        String::from("running service")
+    };
+    if json {
+        print_json(json!({ "applied": name }));
+    } else {
+        println!("applied {name}");
+    }
+    Ok(())
+}
+
+fn export(name: &str, output: Option<&PathBuf>, css: bool, json: bool) -> anyhow::Result<()> {
+    let theme = ColorOverrides::load_from_name(name)?;
+    let contents = if css {
+        theme.resolve()?.as_css(CssMode::Symbolic)
+    } else {
+        theme.to_ron()?
+    };
+    if let Some(path) = output {
+        atomic::write(path, &contents)?;
+    }
+    match (output, json) {
+        (Some(path), true) => print_json(json!({ "name": name, "path": path })),
+        (Some(_), false) => {}
+        (None, true) => print_json(json!({ "name": name, "content": contents })),
+        (None, false) => print!("{contents}"),
+    }
+    Ok(())
+}
+
+fn import(file: &PathBuf, name: Option<&str>, force: bool, json: bool) -> anyhow::Result<()> {
+    let s = fs::read_to_string(file)?;
+    let (mut theme, migrated) = ColorOverrides::from_ron_migrated(&s)
+        .map_err(|e| anyhow::anyhow!("{}: {e}", file.display()))?;
+    if let Some(name) = name {
+        theme.name = name.to_string();
+    }
+    if theme.name.is_empty() {
+        theme.name = file
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    if !force && ColorOverrides::find_path(&theme.name)?.is_some() {
+        anyhow::bail!("theme {} already exists, use --force to replace it", theme.name);
+    }
+    let mut warnings = migrated.warnings;
+    if let Err(e) = theme.resolve() {
+        warnings.push(e.to_string());
+    }
+    theme.save()?;
+    if json {
+        print_json(json!({ "name": theme.name, "warnings": warnings }));
+    } else {
+        for w in &warnings {
+            eprintln!("warning: {w}");
+        }
+        println!("imported {}", theme.name);
+    }
+    Ok(())
+}
+
+fn rename(name: &str, new_name: &str, json: bool) -> anyhow::Result<()> {
+    ThemeLibrary::new()?.rename(name, new_name)?;
+    if json {
+        print_json(json!({ "name": name, "new_name": new_name }));
+    } else {
+        println!("renamed {name} to {new_name}");
+    }
+    Ok(())
+}
+
+fn duplicate(name: &str, new_name: &str, json: bool) -> anyhow::Result<()> {
+    ThemeLibrary::new()?.duplicate(name, new_name)?;
+    if json {
+        print_json(json!({ "name": name, "new_name": new_name }));
+    } else {
+        println!("duplicated {name} as {new_name}");
+    }
+    Ok(())
+}
+
+fn delete(name: &str, json: bool) -> anyhow::Result<()> {
+    ThemeLibrary::new()?.delete(name)?;
+    if json {
+        print_json(json!({ "deleted": name }));
+    } else {
+        println!("deleted {name}");
+    }
+    Ok(())
+}
+
+fn copy(name: &str, json: bool) -> anyhow::Result<()> {
+    ThemeLibrary::new()?.copy_to_user(name)?;
+    if json {
+        print_json(json!({ "copied": name }));
+    } else {
+        println!("copied {name} to the user's themes");
+    }
+    Ok(())
+}
+
+fn validate(themes: &[String], json: bool) -> anyhow::Result<ExitCode> {
+    let mut results = Vec::new();
+    for theme in themes {
+        // arguments are file paths if they exist, and theme names otherwise
+        let path = match PathBuf::from(theme) {
+            p if p.is_file() => Some(p),
+            _ => ColorOverrides::find_path(theme)?,
+        };
+        let res = match &path {
+            Some(p) => fs::read_to_string(p)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| ColorOverrides::from_ron_migrated(&s))
+                .and_then(|(t, migrated)| t.resolve().map(|_| migrated.warnings)),
+            None => ColorOverrides::builtin(theme)
+                .map(|_| Vec::new())
+                .ok_or_else(|| anyhow::anyhow!("Could not find theme {theme}")),
+        };
+        results.push(match res {
+            Ok(warnings) => json!({ "theme": theme, "valid": true, "warnings": warnings }),
+            Err(e) => json!({ "theme": theme, "valid": false, "error": format!("{e:#}") }),
+        });
+    }
+
+    let valid = results.iter().all(|r| r["valid"] == true);
+    if json {
+        print_json(Value::Array(results));
+    } else {
+        for r in &results {
+            let theme = r["theme"].as_str().unwrap_or_default();
+            match r["error"].as_str() {
+                Some(e) => println!("{theme}: error: {e}"),
+                None => {
+                    println!("{theme}: ok");
+                    for w in r["warnings"].as_array().into_iter().flatten() {
+                        println!("{theme}: warning: {}", w.as_str().unwrap_or_default());
+                    }
+                }
+            }
+        }
+    }
+    Ok(if valid {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn status(json: bool) -> anyhow::Result<()> {
+    let config = Config::load()?;
+    let pid = user_colors::running_pid();
+    let scheme = config.color_scheme().ok();
+    let active = scheme.map(|s| config.theme_name(s).to_string());
+    let outputs: Vec<_> = config
+        .outputs
+        .iter()
+        .map(|o| {
+            json!({
+                "format": format!("{:?}", o.format),
+                "path": o.path().ok(),
+                "enabled": o.enabled,
+            })
+        })
+        .collect();
+    let apps: serde_json::Map<_, _> = config
+        .apps
+        .iter()
+        .map(|(id, themes)| {
+            let app = json!({
+                "light": themes.light,
+                "dark": themes.dark,
+                "path": themes.path(id).ok(),
+                "active": scheme.map(|s| config.app_theme_name(id, s).to_string()),
+            });
+            (id.clone(), app)
+        })
+        .collect();
+    let scheme = scheme.map(|s| match s {
+        ColorScheme::Light => "light",
+        ColorScheme::Dark => "dark",
+    });
+
+    if json {
+        print_json(json!({
+            "running": pid.is_some(),
+            "pid": pid,
+            "color_scheme_backend": format!("{:?}", config.color_scheme_backend),
+            "schedule": config.schedule,
+            "color_scheme": scheme,
+            "light": config.light,
+            "dark": config.dark,
+            "active": active,
+            "outputs": outputs,
+            "apps": apps,
+            "flatpak": config.flatpak,
+        }));
+        return Ok(());
+    }
+    match pid {
+        Some(pid) => println!("service: running (pid {pid})"),
+        None => println!("service: not running"),
+    }
+    let scheme_source = match config.schedule {
+        Some(_) => String::from("schedule"),
+        None => format!("{:?}", config.color_scheme_backend),
+    };
+    println!(
+        "color scheme: {} ({scheme_source})",
+        scheme.unwrap_or("unknown")
+    );
+    println!("light theme: {}", config.light);
+    println!("dark theme: {}", config.dark);
+    if let Some(active) = active {
+        println!("active theme: {active}");
+    }
+    println!("outputs:");
+    for o in outputs {
+        println!(
+            "  {} {}{}",
+            o["format"].as_str().unwrap_or_default(),
+            o["path"].as_str().unwrap_or("unknown path"),
+            if o["enabled"] == true { "" } else { " (disabled)" }
+        );
+    }
+    if config.flatpak {
+        println!("flatpak applications: themed");
+    }
+    if !apps.is_empty() {
+        println!("applications:");
+    }
+    for (id, app) in apps {
+        println!(
+            "  {id}: {} {}",
+            app["active"].as_str().unwrap_or("unknown"),
+            app["path"].as_str().unwrap_or("unknown path")
+        );
     }
     Ok(())
 }